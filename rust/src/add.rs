@@ -0,0 +1,710 @@
+//!
+//! Invoked by calling:
+//! `blobtk add <args>`
+
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use anyhow;
+
+use crate::bam::BinnedCov;
+use crate::blobdir::{self, Datatype, Field, FieldMeta, Meta};
+use crate::cli;
+use crate::create::variable_field_meta;
+use crate::error;
+use crate::io::get_reader;
+use crate::taxonomy::parse_taxdump;
+use crate::taxonomy::taxrule::{self, Assignment, Hit, RANKS};
+
+pub use cli::AddOptions;
+
+/// Coverage of each sequence by one read library, summed over windows.
+#[derive(Debug, Default)]
+pub struct LibraryCov {
+    pub library: String,
+    /// Depth multiplied by window width, and total window width
+    base_cov: HashMap<String, (f64, usize)>,
+    read_cov: Option<HashMap<String, usize>>,
+}
+
+impl LibraryCov {
+    fn new(library: &str) -> LibraryCov {
+        LibraryCov {
+            library: library.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn add_window(&mut self, seq_name: &str, start: usize, end: usize, depth: f64) {
+        let entry = self
+            .base_cov
+            .entry(seq_name.to_string())
+            .or_insert((0.0, 0));
+        entry.0 += depth * (end - start) as f64;
+        entry.1 += end - start;
+    }
+
+    fn add_reads(&mut self, seq_name: &str, reads: usize) {
+        *self
+            .read_cov
+            .get_or_insert_with(HashMap::new)
+            .entry(seq_name.to_string())
+            .or_insert(0) += reads;
+    }
+
+    /// Mean depth of a sequence over all of its windows.
+    fn mean_depth(&self, seq_name: &str) -> f64 {
+        match self.base_cov.get(seq_name) {
+            Some((sum, width)) if *width > 0 => (sum / *width as f64 * 10000.0).round() / 10000.0,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Collect per-sequence coverage from `blobtk depth` results.
+pub fn library_covs_from_binned(covs: &[BinnedCov]) -> Vec<LibraryCov> {
+    let mut libraries: Vec<LibraryCov> = vec![];
+    for cov in covs {
+        let index = match libraries.iter().position(|lib| lib.library == cov.library) {
+            Some(index) => index,
+            None => {
+                libraries.push(LibraryCov::new(&cov.library));
+                libraries.len() - 1
+            }
+        };
+        let library = &mut libraries[index];
+        for ((start, end), depth) in cov.starts.iter().zip(cov.ends.iter()).zip(cov.bins.iter()) {
+            library.add_window(&cov.seq_name, *start, *end, *depth);
+        }
+        if !cov.read_counts.is_empty() {
+            library.add_reads(&cov.seq_name, cov.read_counts.iter().sum());
+        }
+    }
+    libraries
+}
+
+fn parse_error(path: &Path, line: usize, message: &str) -> error::Error {
+    error::Error::ParseError(format!("{} line {}: {}", path.display(), line, message))
+}
+
+/// Library name for a BED file without a header row, e.g. `reads.bed.gz`
+/// is named `reads`.
+fn bed_library_name(path: &Path) -> String {
+    let name = path.file_name().unwrap().to_string_lossy().to_string();
+    let name = name.strip_suffix(".gz").unwrap_or(&name);
+    name.strip_suffix(".bed").unwrap_or(name).to_string()
+}
+
+/// Columns in a `blobtk depth` BED header that hold the mean depth or read
+/// count of each library. Other `--stats` columns are skipped.
+fn bed_library_columns(columns: &[&str]) -> Vec<(usize, String, bool)> {
+    let mut library_columns = vec![];
+    let mut library: Option<&str> = None;
+    for (i, column) in columns.iter().enumerate() {
+        let stat = library.and_then(|library| {
+            column
+                .strip_prefix(library)
+                .and_then(|suffix| suffix.strip_prefix('_'))
+        });
+        match stat {
+            Some("reads") => library_columns.push((i, library.unwrap().to_string(), true)),
+            Some(stat)
+                if stat == "median"
+                    || stat == "sd"
+                    || stat
+                        .strip_suffix('x')
+                        .map_or(false, |t| t.parse::<u32>().is_ok()) => {}
+            _ => {
+                library = Some(*column);
+                library_columns.push((i, column.to_string(), false));
+            }
+        }
+    }
+    library_columns
+}
+
+/// Read per-sequence coverage from a `blobtk depth` BED file, with one
+/// library per depth column.
+pub fn library_covs_from_bed(path: &PathBuf) -> Result<Vec<LibraryCov>, error::Error> {
+    let mut libraries: Vec<LibraryCov> = vec![];
+    let mut columns: Vec<(usize, String, bool)> = vec![];
+    for (i, line) in get_reader(path)?.lines().enumerate() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('#') {
+            let header: Vec<&str> = header.split('\t').skip(3).collect();
+            columns = bed_library_columns(&header);
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let row: Vec<&str> = line.split('\t').collect();
+        if row.len() < 4 {
+            return Err(parse_error(path, i + 1, "expected at least four columns"));
+        }
+        if columns.is_empty() {
+            columns = vec![(0, bed_library_name(path), false)];
+        }
+        if libraries.is_empty() {
+            libraries = columns
+                .iter()
+                .filter(|(_, _, reads)| !reads)
+                .map(|(_, library, _)| LibraryCov::new(library))
+                .collect();
+        }
+        let start: usize = row[1]
+            .parse()
+            .map_err(|_| parse_error(path, i + 1, "invalid start"))?;
+        let end: usize = row[2]
+            .parse()
+            .map_err(|_| parse_error(path, i + 1, "invalid end"))?;
+        for (column, library, reads) in columns.iter() {
+            let value = row
+                .get(column + 3)
+                .ok_or_else(|| parse_error(path, i + 1, "missing column"))?;
+            let library = libraries
+                .iter_mut()
+                .find(|lib| &lib.library == library)
+                .unwrap();
+            if *reads {
+                let value = value
+                    .parse()
+                    .map_err(|_| parse_error(path, i + 1, "invalid read count"))?;
+                library.add_reads(row[0], value);
+            } else {
+                let value = value
+                    .parse()
+                    .map_err(|_| parse_error(path, i + 1, "invalid depth"))?;
+                library.add_window(row[0], start, end, value);
+            }
+        }
+    }
+    Ok(libraries)
+}
+
+/// BUSCO statuses recorded in a busco field, in key order.
+const BUSCO_STATUSES: [&str; 3] = ["Complete", "Duplicated", "Fragmented"];
+
+/// Genes found by a BUSCO run, read from a BUSCO v5 `full_table.tsv`.
+#[derive(Debug, Default)]
+pub struct BuscoTable {
+    /// Lineage dataset, e.g. `eukaryota_odb10`
+    pub lineage: String,
+    pub version: Option<String>,
+    /// Number of BUSCO genes in the lineage dataset
+    pub count: usize,
+    /// BUSCO gene ID, status index and sequence name of each gene found
+    pub genes: Vec<(String, usize, String)>,
+}
+
+/// Read a BUSCO v5 `full_table.tsv`, plain or gzipped. The lineage and
+/// version are taken from the comment lines at the top of the file.
+pub fn busco_from_full_table(path: &PathBuf) -> Result<BuscoTable, error::Error> {
+    let mut table = BuscoTable::default();
+    let mut busco_ids = HashSet::new();
+    for (i, line) in get_reader(path)?.lines().enumerate() {
+        let line = line?;
+        if let Some(comment) = line.strip_prefix('#') {
+            let comment = comment.trim();
+            if let Some(version) = comment.strip_prefix("BUSCO version is:") {
+                table.version = Some(version.trim().to_string());
+            } else if let Some(lineage) = comment.strip_prefix("The lineage dataset is:") {
+                table.lineage = lineage.split_whitespace().next().unwrap_or("").to_string();
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let row: Vec<&str> = line.split('\t').collect();
+        if row.len() < 2 {
+            return Err(parse_error(path, i + 1, "expected at least two columns"));
+        }
+        busco_ids.insert(row[0].to_string());
+        let status = match BUSCO_STATUSES.iter().position(|status| *status == row[1]) {
+            Some(status) => status,
+            None if row[1] == "Missing" => continue,
+            None => return Err(parse_error(path, i + 1, "unknown BUSCO status")),
+        };
+        match row.get(2) {
+            Some(seq_name) if !seq_name.is_empty() => {
+                table
+                    .genes
+                    .push((row[0].to_string(), status, seq_name.to_string()))
+            }
+            _ => return Err(parse_error(path, i + 1, "missing sequence name")),
+        }
+    }
+    if table.lineage.is_empty() {
+        return Err(error::Error::ParseError(format!(
+            "{}: lineage dataset not found in header",
+            path.display()
+        )));
+    }
+    table.count = busco_ids.len();
+    Ok(table)
+}
+
+fn values_field<T>(values: Vec<T>, keys: Vec<String>) -> Field<T> {
+    Field {
+        values,
+        keys,
+        category_slot: None,
+        headers: None,
+    }
+}
+
+fn min_max(values: impl Iterator<Item = f64>) -> [f64; 2] {
+    values
+        .map(|value| [value, value])
+        .reduce(|a, b| [a[0].min(b[0]), a[1].max(b[1])])
+        .unwrap_or([0.0, 0.0])
+}
+
+/// Writes a queued field file to a BlobDir.
+type FieldWriter = Box<dyn FnOnce(&Path) -> Result<(), error::Error>>;
+
+/// Fields and metadata to add to a BlobDir. Field IDs are checked as fields
+/// are added but files are only written by `finish`, so a conflicting ID
+/// leaves the BlobDir untouched and `meta.json` is updated last.
+struct BlobDirUpdate {
+    blobdir: PathBuf,
+    meta: Meta,
+    identifiers: Vec<String>,
+    replace: bool,
+    pending: Vec<FieldWriter>,
+}
+
+impl BlobDirUpdate {
+    fn new(blobdir: &PathBuf, replace: bool) -> Result<BlobDirUpdate, error::Error> {
+        let meta = blobdir::parse_blobdir(blobdir)?;
+        let identifiers = blobdir::parse_field_string("identifiers".to_string(), blobdir)?;
+        Ok(BlobDirUpdate {
+            blobdir: blobdir.clone(),
+            meta,
+            identifiers,
+            replace,
+            pending: vec![],
+        })
+    }
+
+    fn check_new(&self, id: &str) -> Result<(), error::Error> {
+        let exists = self
+            .meta
+            .field_list
+            .as_ref()
+            .map_or(false, |fields| fields.contains_key(id));
+        if exists && !self.replace {
+            return Err(error::Error::InvalidOption(format!(
+                "field {} already exists, use --replace to overwrite",
+                id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check a field ID and queue the field file to be written by `finish`.
+    fn queue_field<T: serde::Serialize + 'static>(
+        &mut self,
+        id: &str,
+        field: Field<T>,
+    ) -> Result<(), error::Error> {
+        self.check_new(id)?;
+        let id = id.to_string();
+        self.pending.push(Box::new(move |blobdir: &Path| {
+            blobdir::write_field(blobdir, &id, &field)
+        }));
+        Ok(())
+    }
+
+    fn add<T: serde::Serialize + 'static>(
+        &mut self,
+        field: Field<T>,
+        field_meta: FieldMeta,
+        parent: Option<FieldMeta>,
+    ) -> Result<(), error::Error> {
+        self.queue_field(&field_meta.id, field)?;
+        blobdir::upsert_field_meta(&mut self.meta.fields, field_meta, parent);
+        Ok(())
+    }
+
+    fn add_cov(&mut self, library: &LibraryCov) -> Result<(), error::Error> {
+        let values: Vec<f64> = self
+            .identifiers
+            .iter()
+            .map(|seq_name| library.mean_depth(seq_name))
+            .collect();
+        let range = min_max(values.iter().copied());
+        let id = format!("{}_cov", library.library);
+        let field_meta = FieldMeta {
+            id: id.clone(),
+            name: Some(id.clone()),
+            range: Some(range),
+            ..Default::default()
+        };
+        let mut parent = variable_field_meta(
+            "base_coverage",
+            "Base coverage",
+            Datatype::Float,
+            "scaleLog",
+            range,
+        );
+        parent.clamp = Some(0.01);
+        parent.preload = Some(true);
+        self.add(values_field(values, vec![]), field_meta, Some(parent))?;
+        if self.meta.plot.y.is_none() {
+            self.meta.plot.y = Some(id);
+        }
+
+        if let Some(read_cov) = &library.read_cov {
+            let values: Vec<usize> = self
+                .identifiers
+                .iter()
+                .map(|seq_name| *read_cov.get(seq_name).unwrap_or(&0))
+                .collect();
+            let range = min_max(values.iter().map(|value| *value as f64));
+            let id = format!("{}_read_cov", library.library);
+            let field_meta = FieldMeta {
+                id: id.clone(),
+                name: Some(id),
+                range: Some(range),
+                ..Default::default()
+            };
+            let mut parent = variable_field_meta(
+                "read_coverage",
+                "Read coverage",
+                Datatype::Integer,
+                "scaleLog",
+                range,
+            );
+            parent.clamp = Some(1.0);
+            self.add(values_field(values, vec![]), field_meta, Some(parent))?;
+        }
+        Ok(())
+    }
+
+    /// Add each column of a table as a field, using integer values when every
+    /// value is a whole number, otherwise float values, otherwise categories.
+    /// Sequences missing from the table are set to 0, or `NA` for categories.
+    fn add_column(
+        &mut self,
+        id: &str,
+        column: &HashMap<String, String>,
+    ) -> Result<(), error::Error> {
+        let values: Vec<Option<&String>> = self
+            .identifiers
+            .iter()
+            .map(|seq_name| column.get(seq_name))
+            .collect();
+        let present = || values.iter().flatten();
+        if present().all(|value| value.parse::<usize>().is_ok()) {
+            let values: Vec<usize> = values
+                .iter()
+                .map(|value| value.map_or(0, |v| v.parse().unwrap()))
+                .collect();
+            let range = min_max(values.iter().map(|value| *value as f64));
+            let field_meta = variable_field_meta(id, id, Datatype::Integer, "scaleLinear", range);
+            return self.add(values_field(values, vec![]), field_meta, None);
+        }
+        if present().all(|value| value.parse::<f64>().is_ok()) {
+            let values: Vec<f64> = values
+                .iter()
+                .map(|value| value.map_or(0.0, |v| v.parse().unwrap()))
+                .collect();
+            let range = min_max(values.iter().copied());
+            let field_meta = variable_field_meta(id, id, Datatype::Float, "scaleLinear", range);
+            return self.add(values_field(values, vec![]), field_meta, None);
+        }
+        let mut keys: Vec<String> = vec![];
+        let mut key_indices: HashMap<&str, usize> = HashMap::new();
+        let values: Vec<usize> = values
+            .iter()
+            .map(|value| {
+                let key = value.map_or("NA", |v| v.as_str());
+                *key_indices.entry(key).or_insert_with(|| {
+                    keys.push(key.to_string());
+                    keys.len() - 1
+                })
+            })
+            .collect();
+        let field_meta = FieldMeta {
+            id: id.to_string(),
+            name: Some(id.to_string()),
+            field_type: Some("category".to_string()),
+            datatype: Some(Datatype::String),
+            scale: Some("scaleOrdinal".to_string()),
+            ..Default::default()
+        };
+        self.add(values_field(values, keys), field_meta, None)
+    }
+
+    /// Add a `<lineage>_busco` field listing the BUSCO genes found on each
+    /// sequence, with the gene count and lineage set in `meta.json`.
+    fn add_busco(&mut self, table: &BuscoTable) -> Result<(), error::Error> {
+        let indices: HashMap<&str, usize> = self
+            .identifiers
+            .iter()
+            .enumerate()
+            .map(|(i, seq_name)| (seq_name.as_str(), i))
+            .collect();
+        let mut values: Vec<Vec<(String, usize)>> = vec![vec![]; self.identifiers.len()];
+        let mut unmatched = 0;
+        for (busco_id, status, seq_name) in table.genes.iter() {
+            // some BUSCO v5 releases report the sequence as `name:start-end`
+            let index = indices.get(seq_name.as_str()).or_else(|| {
+                seq_name
+                    .rsplit_once(':')
+                    .and_then(|(name, _)| indices.get(name))
+            });
+            match index {
+                Some(index) => values[*index].push((busco_id.clone(), *status)),
+                None => unmatched += 1,
+            }
+        }
+        if unmatched > 0 {
+            eprintln!(
+                "{} BUSCO genes are on sequences not in the BlobDir",
+                unmatched
+            );
+        }
+        let id = format!("{}_busco", table.lineage);
+        let field = Field {
+            values,
+            keys: BUSCO_STATUSES
+                .iter()
+                .map(|status| status.to_string())
+                .collect(),
+            category_slot: Some(1),
+            headers: Some(vec!["Busco id".to_string(), "Status".to_string()]),
+        };
+        let mut field_meta = FieldMeta {
+            id: id.clone(),
+            name: Some(id),
+            count: Some(table.count),
+            odb_set: Some(table.lineage.clone()),
+            ..Default::default()
+        };
+        if let Some(version) = &table.version {
+            field_meta
+                .other
+                .insert("version".to_string(), serde_json::json!(version));
+        }
+        let parent = FieldMeta {
+            id: "busco".to_string(),
+            field_type: Some("multiarray".to_string()),
+            datatype: Some(Datatype::Mixed),
+            other: HashMap::from([
+                ("category_slot".to_string(), serde_json::json!(1)),
+                (
+                    "headers".to_string(),
+                    serde_json::json!(["Busco id", "Status"]),
+                ),
+            ]),
+            ..Default::default()
+        };
+        self.add(field, field_meta, Some(parent))
+    }
+
+    /// Add `<rule>_<rank>` category fields with linked `_score` and `_cindex`
+    /// fields for each rank, and a `<rule>_positions` field listing the hits
+    /// used for each sequence.
+    fn add_taxrule(
+        &mut self,
+        rule: &str,
+        assignments: &[Vec<Assignment>],
+        positions: Vec<Vec<Hit>>,
+    ) -> Result<(), error::Error> {
+        let mut children = vec![];
+        for (rank_index, rank) in RANKS.iter().enumerate() {
+            let id = format!("{}_{}", rule, rank);
+            let ranked: Vec<&Assignment> = assignments
+                .iter()
+                .map(|assigned| &assigned[rank_index])
+                .collect();
+
+            let mut keys: Vec<String> = vec![];
+            let mut key_indices: HashMap<&str, usize> = HashMap::new();
+            let values: Vec<usize> = ranked
+                .iter()
+                .map(|assignment| {
+                    *key_indices
+                        .entry(assignment.name.as_str())
+                        .or_insert_with(|| {
+                            keys.push(assignment.name.clone());
+                            keys.len() - 1
+                        })
+                })
+                .collect();
+
+            let scores: Vec<f64> = ranked.iter().map(|assignment| assignment.score).collect();
+            let score_id = format!("{}_score", id);
+            let score_meta = variable_field_meta(
+                &score_id,
+                &score_id,
+                Datatype::Float,
+                "scaleLinear",
+                min_max(scores.iter().copied()),
+            );
+            let cindices: Vec<usize> = ranked.iter().map(|assignment| assignment.cindex).collect();
+            let cindex_id = format!("{}_cindex", id);
+            let cindex_meta = variable_field_meta(
+                &cindex_id,
+                &cindex_id,
+                Datatype::Integer,
+                "scaleLinear",
+                min_max(cindices.iter().map(|cindex| *cindex as f64)),
+            );
+            self.queue_field(&id, values_field(values, keys))?;
+            self.queue_field(&score_id, values_field(scores, vec![]))?;
+            self.queue_field(&cindex_id, values_field(cindices, vec![]))?;
+            children.push(FieldMeta {
+                id: id.clone(),
+                name: Some(id),
+                field_type: Some("category".to_string()),
+                datatype: Some(Datatype::String),
+                scale: Some("scaleOrdinal".to_string()),
+                data: Some(vec![cindex_meta, score_meta]),
+                ..Default::default()
+            });
+        }
+
+        let positions_id = format!("{}_positions", rule);
+        let headers = vec![
+            "taxid".to_string(),
+            "start".to_string(),
+            "end".to_string(),
+            "score".to_string(),
+            "subject".to_string(),
+        ];
+        let values: Vec<Vec<(String, usize, usize, f64, String)>> = positions
+            .into_iter()
+            .map(|hits| {
+                hits.into_iter()
+                    .map(|hit| (hit.taxid, hit.start, hit.end, hit.score, hit.subject))
+                    .collect()
+            })
+            .collect();
+        let field = Field {
+            values,
+            keys: vec![],
+            category_slot: None,
+            headers: Some(headers.clone()),
+        };
+        self.queue_field(&positions_id, field)?;
+        let positions_meta = FieldMeta {
+            id: positions_id.clone(),
+            name: Some(positions_id),
+            field_type: Some("multiarray".to_string()),
+            datatype: Some(Datatype::Mixed),
+            other: HashMap::from([("headers".to_string(), serde_json::json!(headers))]),
+            ..Default::default()
+        };
+
+        let rule_meta = FieldMeta {
+            id: rule.to_string(),
+            name: Some(rule.to_string()),
+            children: Some(children),
+            data: Some(vec![positions_meta]),
+            ..Default::default()
+        };
+        let parent = FieldMeta {
+            id: "taxonomy".to_string(),
+            ..Default::default()
+        };
+        blobdir::upsert_field_meta(&mut self.meta.fields, rule_meta, Some(parent));
+        if self.meta.plot.cat.is_none() {
+            self.meta.plot.cat = Some(format!("{}_phylum", rule));
+        }
+        Ok(())
+    }
+
+    /// Add the columns of a TSV file with a header row and sequence IDs in
+    /// the first column.
+    fn add_text(&mut self, path: &PathBuf) -> Result<(), error::Error> {
+        let mut lines = get_reader(path)?.lines();
+        let header = match lines.next() {
+            Some(line) => line?,
+            None => return Err(parse_error(path, 1, "missing header row")),
+        };
+        let ids: Vec<String> = header
+            .trim_start_matches('#')
+            .split('\t')
+            .skip(1)
+            .map(|id| id.trim().replace(' ', "_"))
+            .collect();
+        let mut columns: Vec<HashMap<String, String>> = vec![HashMap::new(); ids.len()];
+        for (i, line) in lines.enumerate() {
+            let line = line?;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut row = line.split('\t');
+            let seq_name = row.next().unwrap().to_string();
+            for (column, value) in columns.iter_mut().zip(row.by_ref()) {
+                if !value.is_empty() {
+                    column.insert(seq_name.clone(), value.to_string());
+                }
+            }
+            if row.next().is_some() {
+                return Err(parse_error(path, i + 2, "more columns than the header"));
+            }
+        }
+        for (id, column) in ids.iter().zip(columns.iter()) {
+            self.add_column(id, column)?;
+        }
+        Ok(())
+    }
+
+    /// Write the queued field files, then `meta.json`.
+    fn finish(self) -> Result<(), error::Error> {
+        for write_field in self.pending {
+            write_field(&self.blobdir)?;
+        }
+        blobdir::write_meta(&self.blobdir, &self.meta)
+    }
+}
+
+/// Add coverage fields from `blobtk depth` results to a BlobDir.
+pub fn add_binned_cov(
+    blobdir: &PathBuf,
+    covs: &[BinnedCov],
+    replace: bool,
+) -> Result<(), error::Error> {
+    let mut update = BlobDirUpdate::new(blobdir, replace)?;
+    for library in library_covs_from_binned(covs) {
+        update.add_cov(&library)?;
+    }
+    update.finish()
+}
+
+/// Execute the `add` subcommand from `blobtk`. Add coverage fields from
+/// `blobtk depth` BED files, fields from TSV columns, BUSCO results and
+/// taxa assigned from BLAST/DIAMOND hits to a BlobDir.
+pub fn add(options: &cli::AddOptions) -> Result<(), anyhow::Error> {
+    let mut update = BlobDirUpdate::new(&options.blobdir, options.replace)?;
+    for path in options.cov.iter() {
+        for library in library_covs_from_bed(path)? {
+            update.add_cov(&library)?;
+        }
+    }
+    for path in options.text.iter() {
+        update.add_text(path)?;
+    }
+    for path in options.busco.iter() {
+        update.add_busco(&busco_from_full_table(path)?)?;
+    }
+    if let Some(taxdump) = &options.taxdump {
+        let nodes = parse_taxdump(taxdump.clone())?;
+        let hit_sets = options
+            .hits
+            .iter()
+            .map(taxrule::parse_hits)
+            .collect::<Result<Vec<_>, _>>()?;
+        let (assignments, positions) =
+            taxrule::taxrule(&update.identifiers, &hit_sets, &nodes, &options.taxrule);
+        update.add_taxrule(&options.taxrule.to_string(), &assignments, positions)?;
+    }
+    update.finish()?;
+    Ok(())
+}
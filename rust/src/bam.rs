@@ -1,15 +1,28 @@
-use std::collections::HashSet;
-use std::io::{ErrorKind, Result, Write};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::create_dir_all;
+use std::io::{self, ErrorKind, Write};
 // use std::ops::Index;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
 
 use indexmap::IndexMap;
 use pyo3::{self, pyclass};
-use rust_htslib::bam::{index, Header, IndexedReader, Read};
+use rust_htslib::bam::record::{Aux, Cigar, Record};
+use rust_htslib::bam::{index, Format, Header, HeaderView, IndexedReader, Read, Reader, Writer};
 use rust_htslib::htslib;
 
-use crate::cli::DepthOptions;
-use crate::io::get_writer;
+use crate::bigwig::BigWigWriter;
+use crate::cli::{DepthEngine, DepthOptions, FilterOptions, PairPolicy, ReadFilter, Supplementary};
+use crate::error;
+use crate::fastq::suffix_file_name;
+use crate::filter::FilterReport;
+use crate::io::{append_to_path, get_writer, FileReport};
+use crate::readset::ReadSet;
 use crate::utils::styled_progress_bar;
 
 fn add_extension(path: &mut PathBuf, extension: impl AsRef<Path>) {
@@ -24,18 +37,44 @@ fn add_extension(path: &mut PathBuf, extension: impl AsRef<Path>) {
     };
 }
 
-pub fn create_index(bam_path: &PathBuf) {
-    let mut csi = PathBuf::from(bam_path);
-    add_extension(&mut csi, "csi");
-    if Path::new(&csi).exists() {
-        return;
+/// Find an existing CSI or BAI index for a BAM/CRAM file, looking in
+/// `index_dir` first when set and then alongside the input file.
+fn find_index(bam_path: &PathBuf, index_dir: &Option<PathBuf>) -> Option<PathBuf> {
+    let mut bases = vec![];
+    if let Some(dir) = index_dir {
+        bases.push(dir.join(bam_path.file_name()?));
+    }
+    bases.push(bam_path.clone());
+    for base in bases {
+        for extension in ["csi", "bai"] {
+            let mut index_path = base.clone();
+            add_extension(&mut index_path, extension);
+            if index_path.exists() {
+                return Some(index_path);
+            }
+        }
     }
-    let mut bai = PathBuf::from(bam_path);
-    add_extension(&mut bai, "bai");
-    if Path::new(&bai).exists() {
+    None
+}
+
+/// Build a CSI index unless one already exists. The index is written
+/// to `index_dir` when set, otherwise alongside the input file.
+pub fn create_index(bam_path: &PathBuf, index_dir: &Option<PathBuf>) {
+    if find_index(bam_path, index_dir).is_some() {
         return;
     }
-    match index::build(bam_path, None, index::Type::Csi(14), 1) {
+    let csi = match index_dir {
+        Some(dir) => {
+            if let Err(e) = create_dir_all(dir) {
+                eprintln!("Error creating index directory: {e:?}");
+            }
+            let mut csi = dir.join(bam_path.file_name().unwrap());
+            add_extension(&mut csi, "csi");
+            Some(csi)
+        }
+        None => None,
+    };
+    match index::build(bam_path, csi.as_ref(), index::Type::Csi(14), 1) {
         Err(e) => eprintln!("Error writing BAM index: {e:?}"),
         Ok(_) => eprintln!("Successfully created BAM index"),
     }
@@ -45,6 +84,7 @@ pub fn open_bam(
     bam_path: &Option<PathBuf>,
     cram_path: &Option<PathBuf>,
     fasta_path: &Option<PathBuf>,
+    index_dir: &Option<PathBuf>,
     make_index: bool,
 ) -> IndexedReader {
     let bam_cram_path = match bam_path {
@@ -52,60 +92,536 @@ pub fn open_bam(
         &Some(_) => bam_path.as_ref().unwrap(),
     };
     if make_index {
-        create_index(bam_cram_path);
+        create_index(bam_cram_path, index_dir);
+    }
+    let mut reader = match find_index(bam_cram_path, index_dir) {
+        Some(index_path) => IndexedReader::from_path_and_index(bam_cram_path, &index_path),
+        None => IndexedReader::from_path(bam_cram_path),
     }
-    let mut reader = IndexedReader::from_path(bam_cram_path).unwrap();
+    .unwrap();
     if cram_path.is_some() && fasta_path.is_some() {
         reader.set_reference(fasta_path.as_ref().unwrap()).unwrap();
     }
     reader
 }
 
+/// Open a BAM/CRAM file, or STDIN when the path is `-`, for sequential
+/// reading without an index.
+pub fn open_bam_stream(
+    bam_path: &Option<PathBuf>,
+    cram_path: &Option<PathBuf>,
+    fasta_path: &Option<PathBuf>,
+) -> Reader {
+    let bam_cram_path = match bam_path {
+        None => cram_path.as_ref().unwrap(),
+        &Some(_) => bam_path.as_ref().unwrap(),
+    };
+    let mut reader = if bam_cram_path == Path::new("-") {
+        Reader::from_stdin()
+    } else {
+        Reader::from_path(bam_cram_path)
+    }
+    .unwrap();
+    if cram_path.is_some() && fasta_path.is_some() {
+        reader.set_reference(fasta_path.as_ref().unwrap()).unwrap();
+    }
+    reader
+}
+
+fn aux_to_u32(aux: Aux) -> Option<u32> {
+    match aux {
+        Aux::U8(value) => Some(value as u32),
+        Aux::U16(value) => Some(value as u32),
+        Aux::U32(value) => Some(value),
+        Aux::I8(value) if value >= 0 => Some(value as u32),
+        Aux::I16(value) if value >= 0 => Some(value as u32),
+        Aux::I32(value) if value >= 0 => Some(value as u32),
+        _ => None,
+    }
+}
+
+/// Count aligned (M/=/X) bases and alignment columns (M/=/X/I/D) in a CIGAR string.
+fn aligned_bases(record: &Record) -> (usize, usize) {
+    let mut aligned = 0;
+    let mut columns = 0;
+    for op in record.cigar().iter() {
+        match op {
+            Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                aligned += *len as usize;
+                columns += *len as usize;
+            }
+            Cigar::Ins(len) | Cigar::Del(len) => columns += *len as usize,
+            _ => (),
+        }
+    }
+    (aligned, columns)
+}
+
+impl ReadFilter {
+    /// Check whether a record passes the flag, mapping quality,
+    /// aligned length and identity filters.
+    pub fn keep(&self, record: &Record) -> bool {
+        let flags = record.flags();
+        if flags & self.include_flags != self.include_flags {
+            return false;
+        }
+        let mut exclude_flags = self.exclude_flags;
+        if self.supplementary == Supplementary::Exclude {
+            exclude_flags |= htslib::BAM_FSUPPLEMENTARY as u16;
+        }
+        if flags & exclude_flags != 0 {
+            return false;
+        }
+        if record.mapq() < self.min_mapq {
+            return false;
+        }
+        if self.min_aligned_length == 0 && self.min_identity <= 0.0 {
+            return true;
+        }
+        let (aligned, columns) = aligned_bases(record);
+        if aligned < self.min_aligned_length {
+            return false;
+        }
+        if self.min_identity > 0.0 {
+            let edit_distance = match record.aux(b"NM").ok().and_then(aux_to_u32) {
+                Some(value) => value,
+                None => return false,
+            };
+            if columns == 0 {
+                return false;
+            }
+            let identity = 1.0 - edit_distance as f64 / columns as f64;
+            if identity < self.min_identity {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Check whether the filter only applies the default flag mask,
+    /// in which case pileup depths can be used without re-checking reads.
+    pub fn is_default(&self) -> bool {
+        *self == ReadFilter::default()
+    }
+}
+
+impl PairPolicy {
+    /// Check whether the mate of a record aligned to a listed sequence
+    /// allows the read to be selected. Unpaired reads are always allowed.
+    pub fn keep_mate(&self, record: &Record, tids: &HashSet<i32>) -> bool {
+        if !record.is_paired() {
+            return true;
+        }
+        match self {
+            PairPolicy::Either => true,
+            PairPolicy::Both => !record.is_mate_unmapped() && tids.contains(&record.mtid()),
+            PairPolicy::BothOrUnmapped => {
+                record.is_mate_unmapped() || tids.contains(&record.mtid())
+            }
+        }
+    }
+}
+
+/// Header target IDs of the listed sequences.
+fn listed_tids(seq_names: &HashSet<Vec<u8>>, header: &HeaderView) -> HashSet<i32> {
+    seq_names
+        .iter()
+        .filter_map(|seq_name| header.tid(seq_name))
+        .map(|tid| tid as i32)
+        .collect()
+}
+
+/// Record which listed sequences are present in the header, warning about
+/// any that are missing.
+fn report_contigs(seq_names: &HashSet<Vec<u8>>, header: &HeaderView, report: &mut FilterReport) {
+    let mut missing: Vec<String> = seq_names
+        .iter()
+        .filter(|seq_name| header.tid(seq_name).is_none())
+        .map(|seq_name| String::from_utf8_lossy(seq_name).to_string())
+        .collect();
+    missing.sort();
+    for seq_name in missing.iter() {
+        eprintln!("Sequence {:?} not found in BAM file", seq_name)
+    }
+    report.contigs_found = seq_names.len() - missing.len();
+    report.contigs_missing = missing;
+}
+
+fn tid_name(header: &HeaderView, tid: i32) -> String {
+    if tid < 0 {
+        return "*".to_string();
+    }
+    String::from_utf8_lossy(header.tid2name(tid as u32)).to_string()
+}
+
 pub fn reads_from_bam<F: Fn()>(
     seq_names: &HashSet<Vec<u8>>,
     mut bam: IndexedReader,
+    read_filter: &ReadFilter,
+    pairs: &PairPolicy,
+    report: &mut FilterReport,
     callback: &Option<F>,
-) -> HashSet<Vec<u8>> {
-    let mut wanted_reads = HashSet::new();
+) -> ReadSet {
+    let tids = listed_tids(seq_names, bam.header());
+    report_contigs(seq_names, bam.header(), report);
+    let mut wanted_reads = ReadSet::new();
     let total = seq_names.len();
     let progress_bar = styled_progress_bar(total, "Locating alignments");
 
     for seq_name in seq_names {
         if bam.fetch(seq_name).is_err() {
-            eprintln!("Sequence {:?} not found in BAM file", seq_name)
+            progress_bar.inc(1);
+            continue;
         }
 
+        let contig = String::from_utf8_lossy(seq_name).to_string();
         for read in bam
             .rc_records()
             .map(|x| x.expect("Failure parsing Bam file"))
-            // TODO: include filter options in config
-            .filter(|read| {
-                read.flags()
-                    & (htslib::BAM_FUNMAP
-                        | htslib::BAM_FSECONDARY
-                        | htslib::BAM_FQCFAIL
-                        | htslib::BAM_FDUP) as u16
-                    == 0
-            })
+            .filter(|read| read_filter.keep(read) && pairs.keep_mate(read, &tids))
         {
-            wanted_reads.insert(read.qname().to_vec());
+            if wanted_reads.insert(read.qname()) {
+                report.add_read(&contig);
+            }
         }
 
         match callback {
             Some(cb) => cb(),
             None => (),
         }
+        progress_bar.set_message(wanted_reads.memory_message());
         progress_bar.inc(1);
     }
     progress_bar.finish();
+    report.reads_selected = wanted_reads.len();
     wanted_reads
 }
 
+/// Names of all sequences in the header that are not in `seq_names`.
+pub fn invert_seq_names(seq_names: &HashSet<Vec<u8>>, header: &HeaderView) -> HashSet<Vec<u8>> {
+    header
+        .target_names()
+        .into_iter()
+        .filter(|name| !seq_names.contains(*name))
+        .map(|name| name.to_vec())
+        .collect()
+}
+
+/// Collect the names of all reads except those that would be selected for
+/// the listed sequences by the read filter and pair policy. Unmapped reads are kept.
+/// Every record is read in turn so an index is not needed.
+pub fn reads_from_bam_inverted<F: Fn()>(
+    seq_names: &HashSet<Vec<u8>>,
+    mut bam: Reader,
+    read_filter: &ReadFilter,
+    pairs: &PairPolicy,
+    report: &mut FilterReport,
+    callback: &Option<F>,
+) -> ReadSet {
+    let tids = listed_tids(seq_names, bam.header());
+    report_contigs(seq_names, bam.header(), report);
+    let mut all_reads = ReadSet::new();
+    // sequence each read in all_reads was first seen on
+    let mut first_tids: Vec<i32> = vec![];
+    let mut unwanted_reads = ReadSet::new();
+    let mut record = Record::new();
+    let mut count: usize = 0;
+    let progress_bar = styled_progress_bar(0, "Reading alignments");
+    while let Some(result) = bam.read(&mut record) {
+        result.expect("Failure parsing Bam file");
+        if !record.is_unmapped()
+            && tids.contains(&record.tid())
+            && read_filter.keep(&record)
+            && pairs.keep_mate(&record, &tids)
+        {
+            unwanted_reads.insert(record.qname());
+        } else if all_reads.insert(record.qname()) {
+            first_tids.push(record.tid());
+        }
+        count += 1;
+        if count % 100_000 == 0 {
+            match callback {
+                Some(cb) => cb(),
+                None => (),
+            }
+            progress_bar.set_position(count as u64);
+            progress_bar.set_message(all_reads.memory_message());
+        }
+    }
+    progress_bar.finish();
+    let wanted_reads = all_reads.difference(&unwanted_reads);
+    for (name, tid) in all_reads.iter().zip(first_tids) {
+        if !unwanted_reads.contains(name) {
+            report.add_read(&tid_name(bam.header(), tid));
+        }
+    }
+    report.reads_selected = wanted_reads.len();
+    wanted_reads
+}
+
+/// Collect the names of reads aligned to the listed sequences by reading
+/// every record in turn, so unindexed and name-sorted input can be used.
+pub fn reads_from_bam_stream<F: Fn()>(
+    seq_names: &HashSet<Vec<u8>>,
+    mut bam: Reader,
+    read_filter: &ReadFilter,
+    pairs: &PairPolicy,
+    report: &mut FilterReport,
+    callback: &Option<F>,
+) -> ReadSet {
+    let tids = listed_tids(seq_names, bam.header());
+    report_contigs(seq_names, bam.header(), report);
+    let mut wanted_reads = ReadSet::new();
+    let mut record = Record::new();
+    let mut count: usize = 0;
+    let progress_bar = styled_progress_bar(0, "Reading alignments");
+    while let Some(result) = bam.read(&mut record) {
+        result.expect("Failure parsing Bam file");
+        if tids.contains(&record.tid())
+            && read_filter.keep(&record)
+            && pairs.keep_mate(&record, &tids)
+            && wanted_reads.insert(record.qname())
+        {
+            report.add_read(&tid_name(bam.header(), record.tid()));
+        }
+        count += 1;
+        if count % 100_000 == 0 {
+            match callback {
+                Some(cb) => cb(),
+                None => (),
+            }
+            progress_bar.set_position(count as u64);
+            progress_bar.set_message(wanted_reads.memory_message());
+        }
+    }
+    progress_bar.finish();
+    report.reads_selected = wanted_reads.len();
+    wanted_reads
+}
+
+/// Assign reads to bins in a single pass over a BAM/CRAM file, using the bin
+/// of the sequence each read maps to. When mates or alignments map to
+/// different bins, the primary alignment of the first read in the pair wins.
+/// The pair policy treats every sequence assigned to a bin as listed.
+pub fn read_bins_from_bam<F: Fn()>(
+    seq_bins: &HashMap<Vec<u8>, usize>,
+    mut bam: Reader,
+    read_filter: &ReadFilter,
+    pairs: &PairPolicy,
+    callback: &Option<F>,
+) -> HashMap<Vec<u8>, usize> {
+    let tid_bins: Vec<Option<usize>> = bam
+        .header()
+        .target_names()
+        .iter()
+        .map(|name| seq_bins.get(*name).copied())
+        .collect();
+    let tids: HashSet<i32> = tid_bins
+        .iter()
+        .enumerate()
+        .filter(|(_, bin)| bin.is_some())
+        .map(|(tid, _)| tid as i32)
+        .collect();
+    let mut read_bins = HashMap::new();
+    let mut record = Record::new();
+    let mut count: usize = 0;
+    while let Some(result) = bam.read(&mut record) {
+        result.expect("Failure parsing Bam file");
+        if record.tid() >= 0 && read_filter.keep(&record) && pairs.keep_mate(&record, &tids) {
+            if let Some(bin) = tid_bins[record.tid() as usize] {
+                let preferred = !record.is_secondary()
+                    && !record.is_supplementary()
+                    && (!record.is_paired() || record.is_first_in_template());
+                if preferred {
+                    read_bins.insert(record.qname().to_vec(), bin);
+                } else {
+                    read_bins.entry(record.qname().to_vec()).or_insert(bin);
+                }
+            }
+        }
+        count += 1;
+        if count % 100_000 == 0 {
+            match callback {
+                Some(cb) => cb(),
+                None => (),
+            }
+        }
+    }
+    read_bins
+}
+
+/// Writes records of selected reads to a BAM/CRAM file with a header
+/// reduced to the kept sequences.
+struct FilteredWriter {
+    writer: Writer,
+    tids: Vec<Option<i32>>,
+    keep_unmapped: bool,
+    report: FileReport,
+}
+
+impl FilteredWriter {
+    fn new(
+        header: &HeaderView,
+        kept_names: &HashSet<Vec<u8>>,
+        keep_unmapped: bool,
+        out_path: &PathBuf,
+        fasta_path: &Option<PathBuf>,
+    ) -> Result<FilteredWriter, error::Error> {
+        let mut tids = vec![];
+        let mut next_tid = 0;
+        for name in header.target_names() {
+            if kept_names.contains(name) {
+                tids.push(Some(next_tid));
+                next_tid += 1;
+            } else {
+                tids.push(None);
+            }
+        }
+        let text = String::from_utf8_lossy(header.as_bytes()).to_string();
+        let mut reduced_text = String::new();
+        for line in text.lines() {
+            if let Some(fields) = line.strip_prefix("@SQ\t") {
+                let seq_name = fields
+                    .split('\t')
+                    .find_map(|field| field.strip_prefix("SN:"))
+                    .unwrap_or("");
+                if !kept_names.contains(seq_name.as_bytes()) {
+                    continue;
+                }
+            }
+            reduced_text.push_str(line);
+            reduced_text.push('\n');
+        }
+        let reduced_header =
+            Header::from_template(&HeaderView::from_bytes(reduced_text.as_bytes()));
+        let is_cram = out_path.extension() == Some(OsStr::new("cram"));
+        let format = if is_cram { Format::Cram } else { Format::Bam };
+        if let Some(parent) = out_path.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut writer = Writer::from_path(out_path, &reduced_header, format)?;
+        if is_cram {
+            match fasta_path {
+                Some(fasta) => writer.set_reference(fasta)?,
+                None => {
+                    return Err(error::Error::NotDefined(
+                        "fasta for CRAM output".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(FilteredWriter {
+            writer,
+            tids,
+            keep_unmapped,
+            report: FileReport::new(out_path),
+        })
+    }
+
+    fn new_tid(&self, tid: i32) -> Option<i32> {
+        if tid < 0 {
+            return None;
+        }
+        self.tids.get(tid as usize).copied().flatten()
+    }
+
+    /// Write a record if its read was selected and it lies on a kept
+    /// sequence (or is unplaced and unmapped reads are kept).
+    fn write(&mut self, record: &mut Record, read_names: &ReadSet) -> Result<(), error::Error> {
+        if !read_names.contains(record.qname()) {
+            return Ok(());
+        }
+        match self.new_tid(record.tid()) {
+            Some(tid) => record.set_tid(tid),
+            None if record.tid() < 0 && self.keep_unmapped => (),
+            None => return Ok(()),
+        }
+        match self.new_tid(record.mtid()) {
+            Some(mtid) => record.set_mtid(mtid),
+            None => {
+                record.set_mtid(-1);
+                record.set_mpos(-1);
+                record.set_flags(record.flags() & !(htslib::BAM_FPROPER_PAIR as u16));
+            }
+        }
+        self.writer.write(record)?;
+        self.report.add(record.seq_len());
+        Ok(())
+    }
+}
+
+/// Write the alignments of the selected reads on the listed sequences to the
+/// `--bam-out` file. With `--invert` the complement of the list is written,
+/// along with unmapped reads. The output is indexed once written.
+pub fn write_filtered_bam(
+    options: &FilterOptions,
+    seq_names: &HashSet<Vec<u8>>,
+    read_names: &ReadSet,
+) -> Result<Vec<FileReport>, error::Error> {
+    let out_path = match &options.bam_out {
+        Some(path) => path,
+        None => return Ok(vec![]),
+    };
+    let in_path = options.bam.as_ref().or(options.cram.as_ref()).unwrap();
+    if (options.invert || options.stream) && in_path == Path::new("-") {
+        return Err(error::Error::InvalidOption(
+            "--bam-out needs a second pass so cannot read from STDIN".to_string(),
+        ));
+    }
+    let mut record = Record::new();
+    let file_report = if options.invert || options.stream {
+        let mut bam = open_bam_stream(&options.bam, &options.cram, &options.fasta);
+        let kept_names = if options.invert {
+            invert_seq_names(seq_names, bam.header())
+        } else {
+            seq_names.clone()
+        };
+        let mut writer = FilteredWriter::new(
+            bam.header(),
+            &kept_names,
+            options.invert,
+            out_path,
+            &options.fasta,
+        )?;
+        while let Some(result) = bam.read(&mut record) {
+            result?;
+            writer.write(&mut record, read_names)?;
+        }
+        writer.report
+    } else {
+        let mut bam = open_bam(
+            &options.bam,
+            &options.cram,
+            &options.fasta,
+            &options.index_dir,
+            true,
+        );
+        let header = bam.header().clone();
+        let mut writer = FilteredWriter::new(&header, seq_names, false, out_path, &options.fasta)?;
+        for seq_name in header.target_names() {
+            if !seq_names.contains(seq_name) {
+                continue;
+            }
+            bam.fetch(seq_name)?;
+            while let Some(result) = bam.read(&mut record) {
+                result?;
+                writer.write(&mut record, read_names)?;
+            }
+        }
+        writer.report
+    };
+    match index::build(out_path, None, index::Type::Csi(14), 1) {
+        Err(e) => eprintln!("Error writing BAM index: {e:?}"),
+        Ok(_) => eprintln!("Successfully created BAM index"),
+    }
+    Ok(vec![file_report])
+}
+
 fn seq_lengths_from_header(
-    bam: &IndexedReader,
+    header: &HeaderView,
     seq_names: &HashSet<Vec<u8>>,
 ) -> IndexMap<String, usize> {
-    let header = Header::from_template(bam.header());
+    let header = Header::from_template(header);
     let mut seq_lengths: IndexMap<String, usize> = IndexMap::new();
     for (_, records) in header.to_hashmap() {
         for record in records {
@@ -122,30 +638,294 @@ fn seq_lengths_from_header(
     seq_lengths
 }
 
+fn file_label(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
+
+/// Pair each input file with a library label, falling back to the
+/// file stem when no labels are given.
+pub(crate) fn library_labels<'a>(
+    options: &DepthOptions,
+    paths: impl ExactSizeIterator<Item = &'a PathBuf>,
+) -> Result<Vec<String>, error::Error> {
+    if !options.label.is_empty() && options.label.len() != paths.len() {
+        return Err(error::Error::InvalidOption(format!(
+            "{} labels provided for {} alignment files",
+            options.label.len(),
+            paths.len()
+        )));
+    }
+    Ok(paths
+        .enumerate()
+        .map(|(i, path)| match options.label.get(i) {
+            Some(label) => label.clone(),
+            None => file_label(path),
+        })
+        .collect())
+}
+
+/// Flags of reads that an htslib pileup always skips.
+const PILEUP_MASK: u16 =
+    (htslib::BAM_FUNMAP | htslib::BAM_FSECONDARY | htslib::BAM_FQCFAIL | htslib::BAM_FDUP) as u16;
+
+/// The pileup engine never sees reads with flags in `PILEUP_MASK`, so
+/// reject read filters that would count them rather than ignoring the flags.
+fn check_pileup_filter(options: &DepthOptions) -> Result<(), error::Error> {
+    let read_filter = &options.read_filter;
+    if options.engine == DepthEngine::Pileup
+        && (read_filter.exclude_flags & PILEUP_MASK != PILEUP_MASK
+            || read_filter.include_flags & PILEUP_MASK != 0)
+    {
+        return Err(error::Error::InvalidOption(
+            "--engine pileup always excludes UNMAP,SECONDARY,QCFAIL,DUP reads, use --engine cigar to count them".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Open each BAM/CRAM file passed to `blobtk depth` and pair it with
+/// a library label, falling back to the file stem when no labels are given.
+/// Labels are matched to all `--bam` files, then all `--cram` files, in
+/// the order given, regardless of how the options were interleaved.
+pub fn open_libraries(
+    options: &DepthOptions,
+) -> Result<Vec<(String, IndexedReader)>, error::Error> {
+    check_pileup_filter(options)?;
+    open_library_readers(options, true)
+}
+
+fn open_library_readers(
+    options: &DepthOptions,
+    make_index: bool,
+) -> Result<Vec<(String, IndexedReader)>, error::Error> {
+    let paths: Vec<(Option<PathBuf>, Option<PathBuf>)> = options
+        .bam
+        .iter()
+        .map(|bam| (Some(bam.clone()), None))
+        .chain(options.cram.iter().map(|cram| (None, Some(cram.clone()))))
+        .collect();
+    if paths.is_empty() {
+        return Err(error::Error::NotDefined("bam or cram".to_string()));
+    }
+    let labels = library_labels(
+        options,
+        paths
+            .iter()
+            .map(|(bam_path, cram_path)| bam_path.as_ref().or(cram_path.as_ref()).unwrap()),
+    )?;
+    let mut libraries = vec![];
+    for (label, (bam_path, cram_path)) in labels.into_iter().zip(paths.iter()) {
+        let bam = open_bam(
+            bam_path,
+            cram_path,
+            &options.fasta,
+            &options.index_dir,
+            make_index,
+        );
+        libraries.push((label, bam));
+    }
+    Ok(libraries)
+}
+
+/// Read sequence lengths from the first library header and check that
+/// every other library lists the same sequences, in the same order.
+fn library_seq_lengths(
+    headers: &[(&String, &HeaderView)],
+    seq_names: &HashSet<Vec<u8>>,
+) -> Result<IndexMap<String, usize>, error::Error> {
+    let (first_label, first_header) = headers[0];
+    let seq_lengths = seq_lengths_from_header(first_header, seq_names);
+    for (label, header) in headers.iter().skip(1) {
+        let other_lengths = seq_lengths_from_header(header, seq_names);
+        if !seq_lengths.iter().eq(other_lengths.iter()) {
+            return Err(error::Error::MismatchedHeaders(format!(
+                "{} and {} list different sequences",
+                first_label, label
+            )));
+        }
+    }
+    Ok(seq_lengths)
+}
+
+/// Length of a coverage window, either as a fraction of the sequence
+/// length or as an absolute number of bases.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WindowLength {
+    Fraction(f64),
+    Absolute(usize),
+}
+
+impl WindowLength {
+    /// Values up to 1.0 are treated as fractions, larger values as bases,
+    /// which must be whole numbers.
+    pub fn from_f64(value: f64) -> Result<WindowLength, error::Error> {
+        if value > 0.0 && value <= 1.0 {
+            Ok(WindowLength::Fraction(value))
+        } else if value > 1.0 && value.fract() == 0.0 && value <= usize::MAX as f64 {
+            Ok(WindowLength::Absolute(value as usize))
+        } else {
+            Err(error::Error::InvalidOption(format!(
+                "window length {} must be a fraction (<= 1) or a whole number of bases",
+                value
+            )))
+        }
+    }
+
+    fn to_bases(self, length: usize) -> usize {
+        let bases = match self {
+            WindowLength::Fraction(fraction) => (length as f64 * fraction).round() as usize,
+            WindowLength::Absolute(bases) => bases,
+        };
+        bases.max(1)
+    }
+}
+
+impl fmt::Display for WindowLength {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowLength::Fraction(fraction) => write!(f, "{}", fraction),
+            WindowLength::Absolute(bases) => write!(f, "{}", bases),
+        }
+    }
+}
+
+/// A window size and optional step used to summarise coverage along a sequence.
+/// Windows overlap when the step is shorter than the window size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    pub size: WindowLength,
+    pub step: Option<WindowLength>,
+}
+
+impl Window {
+    /// Window size and step in bases for a sequence of a given length.
+    pub fn bases(&self, length: usize) -> (usize, usize) {
+        let size = self.size.to_bases(length);
+        let step = match self.step {
+            Some(step) => step.to_bases(size),
+            None => size,
+        };
+        (size, step)
+    }
+
+    /// Start and end coordinates of each window along a sequence.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use crate::blobtk::bam::{Window, WindowLength};
+    /// let window = Window {
+    ///     size: WindowLength::Fraction(0.5),
+    ///     step: Some(WindowLength::Fraction(0.5)),
+    /// };
+    /// assert_eq!(window.bounds(10), vec![(0, 5), (3, 8), (6, 10)]);
+    /// let window = Window {
+    ///     size: WindowLength::Absolute(4),
+    ///     step: None,
+    /// };
+    /// assert_eq!(window.bounds(10), vec![(0, 4), (4, 8), (8, 10)]);
+    /// ```
+    pub fn bounds(&self, length: usize) -> Vec<(usize, usize)> {
+        let (size, step) = self.bases(length);
+        let mut bounds = vec![];
+        let mut start = 0;
+        while start < length {
+            let end = start.saturating_add(size).min(length);
+            bounds.push((start, end));
+            if end == length {
+                break;
+            }
+            start += step;
+        }
+        bounds
+    }
+}
+
+/// Set the windows to use for `depth`, falling back to fixed
+/// `bin_size` bins when no window sizes are given.
+pub fn windows_from_options(options: &DepthOptions) -> Result<Vec<Window>, error::Error> {
+    let step = options
+        .window_step
+        .map(WindowLength::from_f64)
+        .transpose()?;
+    if options.window_size.is_empty() {
+        let size = if options.bin_size == usize::MAX {
+            WindowLength::Fraction(1.0)
+        } else {
+            WindowLength::Absolute(options.bin_size)
+        };
+        return Ok(vec![Window { size, step }]);
+    }
+    options
+        .window_size
+        .iter()
+        .map(|size| {
+            Ok(Window {
+                size: WindowLength::from_f64(*size)?,
+                step,
+            })
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug)]
 #[pyclass]
 pub struct BinnedCov {
     #[pyo3(get)]
-    seq_name: String,
+    pub(crate) seq_name: String,
+    #[pyo3(get)]
+    pub(crate) library: String,
+    #[pyo3(get)]
+    pub(crate) window: String,
+    #[pyo3(get)]
+    pub(crate) bins: Vec<f64>,
+    #[pyo3(get)]
+    pub(crate) starts: Vec<usize>,
     #[pyo3(get)]
-    bins: Vec<f64>,
+    pub(crate) ends: Vec<usize>,
     #[pyo3(get)]
-    bin_count: usize,
+    pub(crate) bin_count: usize,
     #[pyo3(get)]
-    last_bin: usize,
+    pub(crate) last_bin: usize,
     #[pyo3(get)]
-    seq_length: usize,
+    pub(crate) seq_length: usize,
     #[pyo3(get)]
-    step: usize,
+    pub(crate) step: usize,
+    #[pyo3(get)]
+    pub(crate) medians: Vec<f64>,
+    #[pyo3(get)]
+    pub(crate) stdevs: Vec<f64>,
+    #[pyo3(get)]
+    pub(crate) thresholds: Vec<u32>,
+    #[pyo3(get)]
+    pub(crate) covered: Vec<Vec<f64>>,
+    #[pyo3(get)]
+    pub(crate) read_counts: Vec<usize>,
+    #[pyo3(get)]
+    pub(crate) read_count: usize,
 }
 
 impl BinnedCov {
     pub fn seq_name(self) -> String {
         self.seq_name
     }
+    pub fn library(self) -> String {
+        self.library
+    }
+    pub fn window(self) -> String {
+        self.window
+    }
     pub fn bins(self) -> Vec<f64> {
         self.bins
     }
+    pub fn starts(self) -> Vec<usize> {
+        self.starts
+    }
+    pub fn ends(self) -> Vec<usize> {
+        self.ends
+    }
     pub fn bin_count(self) -> usize {
         self.bin_count
     }
@@ -158,157 +938,1164 @@ impl BinnedCov {
     pub fn step(self) -> usize {
         self.step
     }
+    pub fn medians(self) -> Vec<f64> {
+        self.medians
+    }
+    pub fn stdevs(self) -> Vec<f64> {
+        self.stdevs
+    }
+    pub fn thresholds(self) -> Vec<u32> {
+        self.thresholds
+    }
+    pub fn covered(self) -> Vec<Vec<f64>> {
+        self.covered
+    }
+    pub fn read_counts(self) -> Vec<usize> {
+        self.read_counts
+    }
+    pub fn read_count(self) -> usize {
+        self.read_count
+    }
+}
+
+/// Depth along one sequence, summed into each coverage window as it is
+/// calculated. Per-base depths are only kept when statistics or per-base
+/// output need them, otherwise each window needs a single counter.
+pub(crate) struct DepthAccumulator {
+    length: usize,
+    bounds: Vec<Vec<(usize, usize)>>,
+    sums: Vec<Vec<u64>>,
+    depths: Option<Vec<u32>>,
+}
+
+impl DepthAccumulator {
+    pub(crate) fn new(length: usize, options: &DepthOptions) -> DepthAccumulator {
+        let bounds: Vec<Vec<(usize, usize)>> = windows_from_options(options)
+            .expect("window sizes are checked before depth is calculated")
+            .iter()
+            .map(|window| window.bounds(length))
+            .collect();
+        let sums = bounds.iter().map(|bounds| vec![0; bounds.len()]).collect();
+        let per_base = options.stats
+            || options.bedgraph.is_some()
+            || options.bigwig.is_some()
+            || options.quantized.is_some();
+        DepthAccumulator {
+            length,
+            bounds,
+            sums,
+            depths: per_base.then(|| vec![0; length]),
+        }
+    }
+
+    /// Add `depth` to each position from `start` up to, but not including, `end`.
+    pub(crate) fn add(&mut self, start: usize, end: usize, depth: u32) {
+        let end = end.min(self.length);
+        if end <= start || depth == 0 {
+            return;
+        }
+        for (bounds, sums) in self.bounds.iter().zip(self.sums.iter_mut()) {
+            let first = bounds.partition_point(|(_, window_end)| *window_end <= start);
+            for ((window_start, window_end), sum) in bounds[first..].iter().zip(&mut sums[first..])
+            {
+                if *window_start >= end {
+                    break;
+                }
+                let overlap = (*window_end).min(end) - (*window_start).max(start);
+                *sum += depth as u64 * overlap as u64;
+            }
+        }
+        if let Some(depths) = self.depths.as_mut() {
+            for value in depths[start..end].iter_mut() {
+                *value += depth;
+            }
+        }
+    }
+
+    pub(crate) fn finish(self, read_starts: Option<Vec<usize>>) -> SeqCoverage {
+        SeqCoverage {
+            depths: self.depths,
+            sums: self.sums,
+            read_starts,
+        }
+    }
+}
+
+/// Turns alignment spans, added in order of start position, into runs of
+/// constant depth, so memory depends on the number of overlapping reads
+/// rather than the sequence length.
+#[derive(Default)]
+pub(crate) struct SpanSweep {
+    ends: BinaryHeap<Reverse<usize>>,
+    last: usize,
+}
+
+impl SpanSweep {
+    pub(crate) fn new() -> SpanSweep {
+        SpanSweep::default()
+    }
+
+    fn advance(&mut self, pos: usize, depths: &mut DepthAccumulator) {
+        while let Some(Reverse(end)) = self.ends.peek().copied() {
+            if end > pos {
+                break;
+            }
+            depths.add(self.last, end, self.ends.len() as u32);
+            self.last = self.last.max(end);
+            self.ends.pop();
+        }
+        depths.add(self.last, pos, self.ends.len() as u32);
+        self.last = self.last.max(pos);
+    }
+
+    pub(crate) fn push(&mut self, start: usize, end: usize, depths: &mut DepthAccumulator) {
+        if end <= start {
+            return;
+        }
+        self.advance(start, depths);
+        self.ends.push(Reverse(end));
+    }
+
+    pub(crate) fn finish(mut self, depths: &mut DepthAccumulator) {
+        self.advance(usize::MAX, depths);
+    }
+}
+
+/// Depth from a pileup. Reads with flags in the default exclude mask are
+/// always skipped by the pileup, other filters are applied per read.
+fn depth_from_pileup(
+    bam: &mut IndexedReader,
+    seq_name: &String,
+    read_filter: &ReadFilter,
+    depths: &mut DepthAccumulator,
+) {
+    if bam.fetch(seq_name).is_err() {
+        eprintln!("Sequence {:?} not found in BAM file", seq_name)
+    }
+    let filter_reads = !read_filter.is_default();
+    let mut pileups = bam.pileup();
+    // htslib stops adding reads to a pileup at a depth of 8000 by default
+    pileups.set_max_depth(i32::MAX as u32);
+    for p in pileups {
+        let pileup = p.unwrap();
+        let depth = if filter_reads {
+            pileup
+                .alignments()
+                .filter(|alignment| read_filter.keep(&alignment.record()))
+                .count() as u32
+        } else {
+            pileup.depth()
+        };
+        let pos = pileup.pos() as usize;
+        depths.add(pos, pos + 1, depth);
+    }
+}
+
+/// Depth from a single pass over the alignments on a sequence. Each read
+/// adds one to the depth over its full reference span (including deletions
+/// and skipped regions, matching the pileup depth).
+fn depth_from_cigar(
+    bam: &mut IndexedReader,
+    seq_name: &String,
+    read_filter: &ReadFilter,
+    depths: &mut DepthAccumulator,
+) {
+    if bam.fetch(seq_name).is_err() {
+        eprintln!("Sequence {:?} not found in BAM file", seq_name)
+    }
+    let mut sweep = SpanSweep::new();
+    let mut record = Record::new();
+    while let Some(result) = bam.read(&mut record) {
+        result.expect("Failure parsing Bam file");
+        if !read_filter.keep(&record) {
+            continue;
+        }
+        let start = record.pos().max(0) as usize;
+        let end = record.cigar().end_pos().max(0) as usize;
+        sweep.push(start, end, depths);
+    }
+    sweep.finish(depths);
+}
+
+/// Start positions of primary alignments on a sequence that pass the read filter.
+fn primary_read_starts(
+    bam: &mut IndexedReader,
+    seq_name: &String,
+    read_filter: &ReadFilter,
+) -> Vec<usize> {
+    let mut read_starts = vec![];
+    if bam.fetch(seq_name).is_err() {
+        return read_starts;
+    }
+    let mut record = Record::new();
+    while let Some(result) = bam.read(&mut record) {
+        result.expect("Failure parsing Bam file");
+        if record.is_unmapped() || record.is_secondary() || record.is_supplementary() {
+            continue;
+        }
+        if read_filter.keep(&record) {
+            read_starts.push(record.pos().max(0) as usize);
+        }
+    }
+    read_starts
+}
+
+fn window_means(sums: &[u64], bounds: &[(usize, usize)]) -> Vec<f64> {
+    bounds
+        .iter()
+        .zip(sums.iter())
+        .map(|((start, end), sum)| *sum as f64 / (end - start) as f64)
+        .collect()
+}
+
+/// Summed depth in each window for one library on one sequence, with
+/// per-base depths and the start positions of primary reads when extra
+/// statistics or per-base output are requested.
+pub(crate) struct SeqCoverage {
+    depths: Option<Vec<u32>>,
+    sums: Vec<Vec<u64>>,
+    read_starts: Option<Vec<usize>>,
+}
+
+fn seq_coverage(
+    bam: &mut IndexedReader,
+    seq_name: &String,
+    length: usize,
+    options: &DepthOptions,
+) -> SeqCoverage {
+    let mut depths = DepthAccumulator::new(length, options);
+    match options.engine {
+        DepthEngine::Pileup => depth_from_pileup(bam, seq_name, &options.read_filter, &mut depths),
+        DepthEngine::Cigar => depth_from_cigar(bam, seq_name, &options.read_filter, &mut depths),
+    }
+    let read_starts = if options.stats {
+        Some(primary_read_starts(bam, seq_name, &options.read_filter))
+    } else {
+        None
+    };
+    depths.finish(read_starts)
+}
+
+/// Median, standard deviation, fraction of bases covered at each
+/// threshold and number of primary reads starting in a window.
+#[derive(Clone, Debug)]
+struct WindowStats {
+    median: f64,
+    stdev: f64,
+    covered: Vec<f64>,
+    reads: usize,
+}
+
+fn window_stats(
+    depths: &[u32],
+    mean: f64,
+    read_starts: &[usize],
+    thresholds: &[u32],
+) -> WindowStats {
+    let count = depths.len();
+    let mut sorted = depths.to_vec();
+    sorted.sort_unstable();
+    let median = if count == 0 {
+        0.0
+    } else if count % 2 == 0 {
+        (sorted[count / 2 - 1] as f64 + sorted[count / 2] as f64) / 2.0
+    } else {
+        sorted[count / 2] as f64
+    };
+    let variance = if count == 0 {
+        0.0
+    } else {
+        depths
+            .iter()
+            .map(|depth| (*depth as f64 - mean).powi(2))
+            .sum::<f64>()
+            / count as f64
+    };
+    let covered = thresholds
+        .iter()
+        .map(|threshold| {
+            let above = sorted.len() - sorted.partition_point(|depth| depth < threshold);
+            if count == 0 {
+                0.0
+            } else {
+                above as f64 / count as f64
+            }
+        })
+        .collect();
+    WindowStats {
+        median,
+        stdev: variance.sqrt(),
+        covered,
+        reads: read_starts.len(),
+    }
+}
+
+/// Mean depth, and optional extra statistics, for each window on a sequence.
+struct LibraryBins {
+    means: Vec<f64>,
+    stats: Option<Vec<WindowStats>>,
+}
+
+/// Window means and statistics for the `window_index`th window from
+/// `windows_from_options`, which `bounds` must match.
+fn library_bins(
+    coverage: &SeqCoverage,
+    window_index: usize,
+    bounds: &[(usize, usize)],
+    thresholds: &[u32],
+) -> LibraryBins {
+    let means = window_means(&coverage.sums[window_index], bounds);
+    let depths = coverage.depths.as_deref().unwrap_or(&[]);
+    let stats = coverage.read_starts.as_ref().map(|read_starts| {
+        bounds
+            .iter()
+            .zip(means.iter())
+            .map(|((start, end), mean)| {
+                let first = read_starts.partition_point(|pos| pos < start);
+                let last = read_starts.partition_point(|pos| pos < end);
+                window_stats(
+                    &depths[*start..*end],
+                    *mean,
+                    &read_starts[first..last],
+                    thresholds,
+                )
+            })
+            .collect()
+    });
+    LibraryBins { means, stats }
+}
+
+fn suffixed_path(path: &PathBuf, suffix: &str) -> PathBuf {
+    if path.extension().is_none() {
+        append_to_path(path, &format!(".{}", suffix))
+    } else {
+        suffix_file_name(path, &suffix.to_string())
+    }
+}
+
+fn window_bed_path(
+    bed: &Option<PathBuf>,
+    window: &Window,
+    window_count: usize,
+) -> Result<Option<PathBuf>, error::Error> {
+    if window_count == 1 {
+        return Ok(bed.clone());
+    }
+    match bed {
+        Some(path) if path != Path::new("-") => {
+            Ok(Some(suffixed_path(path, &window.size.to_string())))
+        }
+        _ => Err(error::Error::InvalidOption(
+            "a bed file name is required for multiple window sizes".to_string(),
+        )),
+    }
+}
+
+fn library_path(
+    path: &PathBuf,
+    label: &str,
+    library_count: usize,
+) -> Result<PathBuf, error::Error> {
+    if library_count == 1 {
+        return Ok(path.clone());
+    }
+    if path == Path::new("-") {
+        return Err(error::Error::InvalidOption(
+            "a file name is required for per-base output from multiple libraries".to_string(),
+        ));
+    }
+    Ok(suffixed_path(path, label))
+}
+
+/// A depth class for quantized output, covering depths from `min`
+/// up to, but not including, `max`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepthClass {
+    pub min: u32,
+    pub max: Option<u32>,
+    pub label: String,
+}
+
+/// Parse `mosdepth` style depth class boundaries. A trailing `:`
+/// adds an open-ended class above the last boundary.
+///
+/// # Examples
+///
+/// ```
+/// # use crate::blobtk::bam::parse_quantize;
+/// let classes = parse_quantize("0:1:5:150:").unwrap();
+/// let labels: Vec<String> = classes.into_iter().map(|class| class.label).collect();
+/// assert_eq!(labels, vec!["0", "1-4", "5-149", "150+"]);
+/// assert!(parse_quantize("5:1").is_err());
+/// ```
+pub fn parse_quantize(breaks: &str) -> Result<Vec<DepthClass>, error::Error> {
+    let parts: Vec<&str> = breaks.split(':').collect();
+    let open_ended = parts.len() > 1 && parts.last() == Some(&"");
+    let mut values: Vec<u32> = vec![];
+    for part in parts.iter().filter(|part| !part.is_empty()) {
+        values.push(part.parse::<u32>().map_err(|_| {
+            error::Error::InvalidOption(format!("invalid quantize boundary {}", part))
+        })?);
+    }
+    if values.is_empty() || values.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(error::Error::InvalidOption(format!(
+            "quantize boundaries must be increasing: {}",
+            breaks
+        )));
+    }
+    let mut classes: Vec<DepthClass> = values
+        .windows(2)
+        .map(|pair| DepthClass {
+            min: pair[0],
+            max: Some(pair[1]),
+            label: if pair[1] - pair[0] == 1 {
+                format!("{}", pair[0])
+            } else {
+                format!("{}-{}", pair[0], pair[1] - 1)
+            },
+        })
+        .collect();
+    if open_ended {
+        let min = *values.last().unwrap();
+        classes.push(DepthClass {
+            min,
+            max: None,
+            label: format!("{}+", min),
+        });
+    }
+    Ok(classes)
+}
+
+fn depth_class(depth: u32, classes: &[DepthClass]) -> Option<usize> {
+    classes.iter().position(|class| match class.max {
+        Some(max) => depth >= class.min && depth < max,
+        None => depth >= class.min,
+    })
+}
+
+/// Merge runs of adjacent positions with equal values into (start, end, value) regions.
+fn merge_runs<T: PartialEq + Copy>(values: impl Iterator<Item = T>) -> Vec<(usize, usize, T)> {
+    let mut runs: Vec<(usize, usize, T)> = vec![];
+    for (pos, value) in values.enumerate() {
+        match runs.last_mut() {
+            Some((_, end, last)) if *last == value => *end = pos + 1,
+            _ => runs.push((pos, pos + 1, value)),
+        }
+    }
+    runs
+}
+
+/// Per-base depth runs and quantized regions for one library on one sequence.
+struct BaseRuns {
+    depths: Option<Vec<(usize, usize, u32)>>,
+    classes: Option<Vec<(usize, usize, usize)>>,
+}
+
+fn base_runs(depths: &[u32], options: &DepthOptions, classes: &[DepthClass]) -> BaseRuns {
+    BaseRuns {
+        depths: if options.bedgraph.is_some() || options.bigwig.is_some() {
+            Some(
+                merge_runs(depths.iter().copied())
+                    .into_iter()
+                    .filter(|(_, _, depth)| *depth > 0)
+                    .collect(),
+            )
+        } else {
+            None
+        },
+        classes: if options.quantized.is_some() {
+            Some(
+                merge_runs(depths.iter().map(|depth| depth_class(*depth, classes)))
+                    .into_iter()
+                    .filter_map(|(start, end, class)| class.map(|class| (start, end, class)))
+                    .collect(),
+            )
+        } else {
+            None
+        },
+    }
+}
+
+/// Per-library writers for bedGraph, bigWig and quantized output.
+struct TrackWriters {
+    bedgraph: Option<Box<dyn Write>>,
+    bigwig: Option<BigWigWriter>,
+    quantized: Option<Box<dyn Write>>,
+}
+
+impl TrackWriters {
+    fn new(
+        options: &DepthOptions,
+        label: &str,
+        library_count: usize,
+        seq_lengths: &IndexMap<String, usize>,
+    ) -> Result<TrackWriters, error::Error> {
+        let bedgraph = match &options.bedgraph {
+            Some(path) => Some(get_writer(&Some(library_path(path, label, library_count)?))),
+            None => None,
+        };
+        let bigwig = match &options.bigwig {
+            Some(path) if path == Path::new("-") => {
+                return Err(error::Error::InvalidOption(
+                    "bigWig output must be written to a file".to_string(),
+                ))
+            }
+            Some(path) => Some(BigWigWriter::create(
+                &library_path(path, label, library_count)?,
+                seq_lengths,
+            )?),
+            None => None,
+        };
+        let quantized = match &options.quantized {
+            Some(path) => Some(get_writer(&Some(library_path(path, label, library_count)?))),
+            None => None,
+        };
+        Ok(TrackWriters {
+            bedgraph,
+            bigwig,
+            quantized,
+        })
+    }
+
+    fn write(
+        &mut self,
+        seq_name: &String,
+        runs: &BaseRuns,
+        classes: &[DepthClass],
+    ) -> io::Result<()> {
+        if let (Some(writer), Some(depths)) = (self.bedgraph.as_mut(), runs.depths.as_ref()) {
+            for (start, end, depth) in depths {
+                writeln!(writer, "{}\t{}\t{}\t{}", seq_name, start, end, depth)?;
+            }
+        }
+        if let (Some(writer), Some(depths)) = (self.bigwig.as_mut(), runs.depths.as_ref()) {
+            let values: Vec<(usize, usize, f32)> = depths
+                .iter()
+                .map(|(start, end, depth)| (*start, *end, *depth as f32))
+                .collect();
+            writer.write_runs(seq_name, &values)?;
+        }
+        if let (Some(writer), Some(regions)) = (self.quantized.as_mut(), runs.classes.as_ref()) {
+            for (start, end, class) in regions {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}\t{}",
+                    seq_name, start, end, classes[*class].label
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(), error::Error> {
+        if let Some(writer) = self.bigwig {
+            writer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+fn bed_header_columns(label: &str, thresholds: &[u32], stats: bool) -> Vec<String> {
+    let mut columns = vec![label.to_string()];
+    if stats {
+        columns.push(format!("{}_median", label));
+        columns.push(format!("{}_sd", label));
+        for threshold in thresholds {
+            columns.push(format!("{}_{}x", label, threshold));
+        }
+        columns.push(format!("{}_reads", label));
+    }
+    columns
+}
+
+fn write_bed_header(columns: &[String], writer: &mut Box<dyn Write>) -> io::Result<()> {
+    writeln!(writer, "#seq_name\tstart\tend\t{}", columns.join("\t"))?;
+    Ok(())
 }
 
 fn depth_to_bed(
-    raw_cov: Vec<usize>,
-    length: &usize,
-    step: usize,
+    library_bins: &[LibraryBins],
+    bounds: &[(usize, usize)],
     seq_name: &String,
     writer: &mut Box<dyn Write>,
-) -> Result<()> {
-    let mut bins: Vec<f64> = vec![];
-    let mut divisor = step;
-    let mut end: usize = 0;
-    let seq_length = length.to_owned();
-    for cov in raw_cov {
-        end += step;
-        if end > seq_length {
-            divisor -= end - seq_length;
-        }
-        bins.push(cov as f64 / divisor as f64);
-    }
-    let mut start = 0;
-    let mut end;
-    let bin_count = bins.len();
-    for bin in bins.iter().take(bin_count) {
-        end = start + step;
-        if end > seq_length {
-            end = seq_length;
-        }
-        let line = format!("{}\t{}\t{}\t{:.2}", seq_name, start, end, bin);
+) -> io::Result<()> {
+    for (i, (start, end)) in bounds.iter().enumerate() {
+        let mut covs: Vec<String> = vec![];
+        for bins in library_bins {
+            covs.push(format!("{:.2}", bins.means[i]));
+            if let Some(stats) = &bins.stats {
+                covs.push(format!("{:.2}", stats[i].median));
+                covs.push(format!("{:.2}", stats[i].stdev));
+                for covered in stats[i].covered.iter() {
+                    covs.push(format!("{:.4}", covered));
+                }
+                covs.push(format!("{}", stats[i].reads));
+            }
+        }
+        let line = format!("{}\t{}\t{}\t{}", seq_name, start, end, covs.join("\t"));
         writeln!(writer, "{}", line)?;
-        start = end;
     }
     Ok(())
 }
 
-pub fn bed_from_bam<F: Fn()>(
+/// Sets a flag when dropped so worker threads stop early if the
+/// main thread returns or unwinds (e.g. on a Python KeyboardInterrupt).
+struct CancelOnDrop<'a>(&'a AtomicBool);
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Run `work` for each sequence and pass the results to `handle` in header
+/// order. With more than one thread, each worker calls `open` to get its own
+/// copy of the alignment source (e.g. indexed readers) and takes the next
+/// unprocessed sequence. The callback, progress bar and `handle` always run
+/// on the calling thread. `handle` returns `false` to stop early.
+fn process_sequences<S, O, T, W, H, F>(
     seq_lengths: &IndexMap<String, usize>,
-    mut bam: IndexedReader,
+    mut source: S,
+    open: O,
     options: &DepthOptions,
+    work: W,
+    mut handle: H,
     callback: &Option<F>,
-) {
+) where
+    O: Fn() -> S + Sync,
+    T: Send,
+    W: Fn(&mut S, &String, usize) -> T + Sync,
+    H: FnMut(&String, T) -> bool,
+    F: Fn(),
+{
     let total = seq_lengths.len();
     let progress_bar = styled_progress_bar(total, "Locating alignments");
-    let bin_size = options.bin_size;
-    let step = bin_size;
-    let mut writer = get_writer(&options.bed);
-    for (seq_name, length) in seq_lengths.clone() {
-        let mut raw_cov: Vec<usize> = vec![];
-        for _ in (0..length).step_by(step) {
-            raw_cov.push(0)
-        }
-        if bam.fetch(&seq_name).is_err() {
-            eprintln!("Sequence {:?} not found in BAM file", seq_name)
-        }
-        for p in bam.pileup() {
-            let pileup = p.unwrap();
-            let bin = pileup.pos() as usize / step;
-            raw_cov[bin] += pileup.depth() as usize;
-        }
-        match callback {
-            Some(cb) => cb(),
-            None => (),
+    if options.threads <= 1 {
+        for (seq_name, length) in seq_lengths.iter() {
+            let result = work(&mut source, seq_name, *length);
+            match callback {
+                Some(cb) => cb(),
+                None => (),
+            }
+            if !handle(seq_name, result) {
+                return;
+            }
+            progress_bar.inc(1);
         }
-        match depth_to_bed(raw_cov, &length, step, &seq_name, &mut writer) {
-            Err(err) if err.kind() == ErrorKind::BrokenPipe => return,
-            Err(err) => panic!("unable to write {} to bed file: {}", &seq_name, err),
-            Ok(_) => (),
-        };
-        progress_bar.inc(1);
+        progress_bar.finish();
+        return;
     }
+    drop(source);
+    let seqs: Vec<(&String, &usize)> = seq_lengths.iter().collect();
+    let next = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..options.threads.min(total) {
+            let sender = sender.clone();
+            let (seqs, next, cancelled, open, work) = (&seqs, &next, &cancelled, &open, &work);
+            scope.spawn(move || {
+                let mut source = open();
+                while !cancelled.load(Ordering::Relaxed) {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    if i >= seqs.len() {
+                        break;
+                    }
+                    let (seq_name, length) = seqs[i];
+                    let result = work(&mut source, seq_name, *length);
+                    if sender.send((i, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        let _guard = CancelOnDrop(&cancelled);
+        let mut pending = BTreeMap::new();
+        let mut expected = 0;
+        for (i, result) in receiver {
+            pending.insert(i, result);
+            while let Some(result) = pending.remove(&expected) {
+                match callback {
+                    Some(cb) => cb(),
+                    None => (),
+                }
+                if !handle(seqs[expected].0, result) {
+                    return;
+                }
+                progress_bar.inc(1);
+                expected += 1;
+            }
+        }
+    });
     progress_bar.finish();
 }
 
-fn depth_to_cov(raw_cov: Vec<usize>, length: &usize, step: usize, seq_name: &String) -> BinnedCov {
-    let mut bins: Vec<f64> = vec![];
-    let mut divisor = step;
-    let mut end: usize = 0;
-    let seq_length = length.to_owned();
-    for cov in raw_cov {
-        end += step;
-        if end > seq_length {
-            divisor -= end - seq_length;
+/// Write BED and per-base track files for coverage calculated by
+/// `coverages`, which returns one `SeqCoverage` per library label.
+pub(crate) fn write_depth<S, O, C, F>(
+    seq_lengths: &IndexMap<String, usize>,
+    labels: &[String],
+    source: S,
+    open: O,
+    coverages: C,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<(), error::Error>
+where
+    O: Fn() -> S + Sync,
+    C: Fn(&mut S, &String, usize) -> Vec<SeqCoverage> + Sync,
+    F: Fn(),
+{
+    let write_bed = options.bed.is_some()
+        || (options.bedgraph.is_none() && options.bigwig.is_none() && options.quantized.is_none());
+    let windows = windows_from_options(options)?;
+    let windows = if write_bed { windows } else { vec![] };
+    let classes = if options.quantized.is_some() {
+        parse_quantize(&options.quantize)?
+    } else {
+        vec![]
+    };
+    let mut writers = vec![];
+    for window in windows.iter() {
+        let bed_path = window_bed_path(&options.bed, window, windows.len())?;
+        writers.push(get_writer(&bed_path));
+    }
+    let mut tracks = vec![];
+    for label in labels.iter() {
+        tracks.push(TrackWriters::new(
+            options,
+            label,
+            labels.len(),
+            seq_lengths,
+        )?);
+    }
+    if labels.len() > 1 || options.stats {
+        let columns: Vec<String> = labels
+            .iter()
+            .flat_map(|label| bed_header_columns(label, &options.thresholds, options.stats))
+            .collect();
+        for writer in writers.iter_mut() {
+            match write_bed_header(&columns, writer) {
+                Err(err) if err.kind() == ErrorKind::BrokenPipe => return Ok(()),
+                Err(err) => panic!("unable to write bed file header: {}", err),
+                Ok(_) => (),
+            };
         }
-        bins.push(cov as f64 / divisor as f64);
     }
+    process_sequences(
+        seq_lengths,
+        source,
+        open,
+        options,
+        |source, seq_name, length| {
+            let coverages = coverages(source, seq_name, length);
+            let window_bins = windows
+                .iter()
+                .enumerate()
+                .map(|(i, window)| {
+                    let bounds = window.bounds(length);
+                    let bins: Vec<LibraryBins> = coverages
+                        .iter()
+                        .map(|coverage| library_bins(coverage, i, &bounds, &options.thresholds))
+                        .collect();
+                    (bounds, bins)
+                })
+                .collect::<Vec<(Vec<(usize, usize)>, Vec<LibraryBins>)>>();
+            let runs: Vec<BaseRuns> = coverages
+                .iter()
+                .map(|coverage| {
+                    base_runs(coverage.depths.as_deref().unwrap_or(&[]), options, &classes)
+                })
+                .collect();
+            (window_bins, runs)
+        },
+        |seq_name, (window_bins, runs)| {
+            for ((bounds, library_bins), writer) in window_bins.iter().zip(writers.iter_mut()) {
+                match depth_to_bed(library_bins, bounds, seq_name, writer) {
+                    Err(err) if err.kind() == ErrorKind::BrokenPipe => return false,
+                    Err(err) => panic!("unable to write {} to bed file: {}", seq_name, err),
+                    Ok(_) => (),
+                };
+            }
+            for (track, library_runs) in tracks.iter_mut().zip(runs.iter()) {
+                match track.write(seq_name, library_runs, &classes) {
+                    Err(err) if err.kind() == ErrorKind::BrokenPipe => return false,
+                    Err(err) => panic!("unable to write {} to track file: {}", seq_name, err),
+                    Ok(_) => (),
+                };
+            }
+            true
+        },
+        callback,
+    );
+    for track in tracks {
+        track.finish()?;
+    }
+    Ok(())
+}
+
+fn library_coverages(
+    libraries: &mut [(String, IndexedReader)],
+    seq_name: &String,
+    length: usize,
+    options: &DepthOptions,
+) -> Vec<SeqCoverage> {
+    libraries
+        .iter_mut()
+        .map(|(_, bam)| seq_coverage(bam, seq_name, length, options))
+        .collect()
+}
+
+pub fn bed_from_bam<F: Fn()>(
+    seq_lengths: &IndexMap<String, usize>,
+    libraries: Vec<(String, IndexedReader)>,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<(), error::Error> {
+    let labels: Vec<String> = libraries.iter().map(|(label, _)| label.clone()).collect();
+    write_depth(
+        seq_lengths,
+        &labels,
+        libraries,
+        || open_library_readers(options, false).expect("unable to open BAM/CRAM file"),
+        |libraries, seq_name, length| library_coverages(libraries, seq_name, length, options),
+        options,
+        callback,
+    )
+}
+
+fn depth_to_cov(
+    coverage: &SeqCoverage,
+    window_index: usize,
+    window: &Window,
+    length: &usize,
+    seq_name: &String,
+    library: &String,
+    thresholds: &[u32],
+) -> BinnedCov {
+    let seq_length = length.to_owned();
+    let bounds = window.bounds(seq_length);
+    let LibraryBins { means, stats } = library_bins(coverage, window_index, &bounds, thresholds);
+    let (_, step) = window.bases(seq_length);
+    let stats = stats.unwrap_or_default();
     BinnedCov {
         seq_name: seq_name.to_owned(),
+        library: library.to_owned(),
+        window: window.size.to_string(),
         step,
-        bin_count: bins.len(),
-        bins,
+        bin_count: means.len(),
+        bins: means,
+        starts: bounds.iter().map(|(start, _)| *start).collect(),
+        ends: bounds.iter().map(|(_, end)| *end).collect(),
         seq_length,
-        last_bin: divisor,
+        last_bin: bounds.last().map(|(start, end)| end - start).unwrap_or(0),
+        medians: stats.iter().map(|stat| stat.median).collect(),
+        stdevs: stats.iter().map(|stat| stat.stdev).collect(),
+        thresholds: if coverage.read_starts.is_some() {
+            thresholds.to_vec()
+        } else {
+            vec![]
+        },
+        covered: (0..thresholds.len())
+            .filter(|_| !stats.is_empty())
+            .map(|i| stats.iter().map(|stat| stat.covered[i]).collect())
+            .collect(),
+        read_counts: stats.iter().map(|stat| stat.reads).collect(),
+        read_count: coverage
+            .read_starts
+            .as_ref()
+            .map_or(0, |starts| starts.len()),
     }
 }
 
-pub fn depth_from_bam<F: Fn()>(
+/// Collect binned coverage for each library label from the coverage
+/// calculated by `coverages`.
+pub(crate) fn binned_depth<S, O, C, F>(
     seq_lengths: &IndexMap<String, usize>,
-    mut bam: IndexedReader,
+    labels: &[String],
+    source: S,
+    open: O,
+    coverages: C,
     options: &DepthOptions,
     callback: &Option<F>,
-) -> Vec<BinnedCov> {
-    let total = seq_lengths.len();
-    let progress_bar = styled_progress_bar(total, "Locating alignments");
-    let bin_size = options.bin_size;
-    let step = bin_size;
+) -> Result<Vec<BinnedCov>, error::Error>
+where
+    O: Fn() -> S + Sync,
+    C: Fn(&mut S, &String, usize) -> Vec<SeqCoverage> + Sync,
+    F: Fn(),
+{
+    let windows = windows_from_options(options)?;
     let mut binned_covs = vec![];
-    for (seq_name, length) in seq_lengths.clone() {
-        let mut raw_cov: Vec<usize> = vec![];
-        for _ in (0..length).step_by(step) {
-            raw_cov.push(0)
+    process_sequences(
+        seq_lengths,
+        source,
+        open,
+        options,
+        |source, seq_name, length| {
+            let mut covs = vec![];
+            for (label, coverage) in labels.iter().zip(coverages(source, seq_name, length)) {
+                for (i, window) in windows.iter().enumerate() {
+                    covs.push(depth_to_cov(
+                        &coverage,
+                        i,
+                        window,
+                        &length,
+                        seq_name,
+                        label,
+                        &options.thresholds,
+                    ));
+                }
+            }
+            covs
+        },
+        |_, covs| {
+            binned_covs.extend(covs);
+            true
+        },
+        callback,
+    );
+    Ok(binned_covs)
+}
+
+pub fn depth_from_bam<F: Fn()>(
+    seq_lengths: &IndexMap<String, usize>,
+    libraries: Vec<(String, IndexedReader)>,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<Vec<BinnedCov>, error::Error> {
+    let labels: Vec<String> = libraries.iter().map(|(label, _)| label.clone()).collect();
+    binned_depth(
+        seq_lengths,
+        &labels,
+        libraries,
+        || open_library_readers(options, false).expect("unable to open BAM/CRAM file"),
+        |libraries, seq_name, length| library_coverages(libraries, seq_name, length, options),
+        options,
+        callback,
+    )
+}
+
+pub fn get_bed_file<F: Fn()>(
+    libraries: Vec<(String, IndexedReader)>,
+    seq_names: &HashSet<Vec<u8>>,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<(), error::Error> {
+    let headers: Vec<(&String, &HeaderView)> = libraries
+        .iter()
+        .map(|(label, bam)| (label, bam.header()))
+        .collect();
+    let seq_lengths = library_seq_lengths(&headers, seq_names)?;
+    bed_from_bam(&seq_lengths, libraries, options, callback)
+}
+
+pub fn get_depth<F: Fn()>(
+    libraries: Vec<(String, IndexedReader)>,
+    seq_names: &HashSet<Vec<u8>>,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<Vec<BinnedCov>, error::Error> {
+    let headers: Vec<(&String, &HeaderView)> = libraries
+        .iter()
+        .map(|(label, bam)| (label, bam.header()))
+        .collect();
+    let seq_lengths = library_seq_lengths(&headers, seq_names)?;
+    depth_from_bam(&seq_lengths, libraries, options, callback)
+}
+
+/// Sequential reader over a coordinate-sorted BAM/CRAM stream, used to
+/// calculate depth one sequence at a time without an index.
+pub struct BamStream {
+    reader: Reader,
+    header: HeaderView,
+    record: Record,
+    buffered: bool,
+    last_position: (i32, i64),
+}
+
+impl BamStream {
+    pub fn new(reader: Reader) -> BamStream {
+        let header = reader.header().clone();
+        BamStream {
+            reader,
+            header,
+            record: Record::new(),
+            buffered: false,
+            last_position: (0, 0),
         }
-        if bam.fetch(&seq_name).is_err() {
-            eprintln!("Sequence {:?} not found in BAM file", seq_name)
+    }
+
+    /// Read the next record unless one is already buffered, returning
+    /// `false` at the end of the stream.
+    fn fill(&mut self) -> bool {
+        if self.buffered {
+            return true;
         }
-        for p in bam.pileup() {
-            let pileup = p.unwrap();
-            let bin = pileup.pos() as usize / step;
-            raw_cov[bin] += pileup.depth() as usize;
+        match self.reader.read(&mut self.record) {
+            None => false,
+            Some(result) => {
+                result.expect("Failure parsing Bam file");
+                let position = (self.record.tid(), self.record.pos());
+                if position.0 >= 0 {
+                    if position < self.last_position {
+                        panic!("BAM/CRAM stream is not sorted by coordinate");
+                    }
+                    self.last_position = position;
+                }
+                self.buffered = true;
+                true
+            }
         }
-        match callback {
-            Some(cb) => cb(),
-            None => (),
+    }
+
+    /// Depths and primary read starts for a sequence, reading
+    /// records until the first record on a later sequence. Streams always
+    /// use the CIGAR engine as pileups need an index.
+    fn seq_coverage(
+        &mut self,
+        seq_name: &String,
+        length: usize,
+        options: &DepthOptions,
+    ) -> SeqCoverage {
+        let tid = self
+            .header
+            .tid(seq_name.as_bytes())
+            .expect("sequence not found in BAM header") as i32;
+        let mut depths = DepthAccumulator::new(length, options);
+        let mut sweep = SpanSweep::new();
+        let mut read_starts = vec![];
+        while self.fill() {
+            let record = &self.record;
+            if record.tid() > tid {
+                break;
+            }
+            self.buffered = false;
+            if record.tid() < tid || !options.read_filter.keep(record) {
+                continue;
+            }
+            let start = record.pos().max(0) as usize;
+            let end = record.cigar().end_pos().max(0) as usize;
+            sweep.push(start, end, &mut depths);
+            if options.stats
+                && !(record.is_unmapped() || record.is_secondary() || record.is_supplementary())
+            {
+                read_starts.push(start);
+            }
         }
-        binned_covs.push(depth_to_cov(raw_cov, &length, step, &seq_name));
-        // match depth_to_bed(raw_cov, &length, step, &seq_name, &mut writer) {
-        //     Err(err) if err.kind() == ErrorKind::BrokenPipe => return,
-        //     Err(err) => panic!("unable to write {} to bed file: {}", &seq_name, err),
-        //     Ok(_) => (),
-        // };
-        progress_bar.inc(1);
+        sweep.finish(&mut depths);
+        depths.finish(options.stats.then_some(read_starts))
     }
-    progress_bar.finish();
-    binned_covs
 }
 
-pub fn get_bed_file<F: Fn()>(
-    bam: IndexedReader,
+/// Open each BAM/CRAM file (or STDIN) passed to `blobtk depth --stream`
+/// for sequential reading. Input must be sorted by coordinate.
+pub fn open_stream_libraries(
+    options: &DepthOptions,
+) -> Result<Vec<(String, BamStream)>, error::Error> {
+    if options.threads > 1 {
+        return Err(error::Error::InvalidOption(
+            "--stream cannot be used with more than one thread".to_string(),
+        ));
+    }
+    if options.engine == DepthEngine::Pileup {
+        return Err(error::Error::InvalidOption(
+            "--stream cannot be used with --engine pileup, which needs an index".to_string(),
+        ));
+    }
+    let paths: Vec<(Option<PathBuf>, Option<PathBuf>)> = options
+        .bam
+        .iter()
+        .map(|bam| (Some(bam.clone()), None))
+        .chain(options.cram.iter().map(|cram| (None, Some(cram.clone()))))
+        .collect();
+    if paths.is_empty() {
+        return Err(error::Error::NotDefined("bam or cram".to_string()));
+    }
+    let labels = library_labels(
+        options,
+        paths
+            .iter()
+            .map(|(bam_path, cram_path)| bam_path.as_ref().or(cram_path.as_ref()).unwrap()),
+    )?;
+    let mut libraries = vec![];
+    for (label, (bam_path, cram_path)) in labels.into_iter().zip(paths.iter()) {
+        let stream = BamStream::new(open_bam_stream(bam_path, cram_path, &options.fasta));
+        let header = Header::from_template(&stream.header).to_hashmap();
+        let sort_order = header
+            .get("HD")
+            .and_then(|records| records.first())
+            .and_then(|record| record.get("SO"));
+        if let Some(sort_order) = sort_order {
+            if sort_order != "coordinate" {
+                return Err(error::Error::InvalidOption(format!(
+                    "{} is sorted by {}, --stream needs coordinate-sorted input",
+                    label, sort_order
+                )));
+            }
+        }
+        libraries.push((label, stream));
+    }
+    Ok(libraries)
+}
+
+fn stream_coverages(
+    libraries: &mut [(String, BamStream)],
+    seq_name: &String,
+    length: usize,
+    options: &DepthOptions,
+) -> Vec<SeqCoverage> {
+    libraries
+        .iter_mut()
+        .map(|(_, stream)| stream.seq_coverage(seq_name, length, options))
+        .collect()
+}
+
+fn stream_seq_lengths(
+    libraries: &[(String, BamStream)],
+    seq_names: &HashSet<Vec<u8>>,
+) -> Result<IndexMap<String, usize>, error::Error> {
+    let headers: Vec<(&String, &HeaderView)> = libraries
+        .iter()
+        .map(|(label, stream)| (label, &stream.header))
+        .collect();
+    library_seq_lengths(&headers, seq_names)
+}
+
+pub fn get_stream_bed_file<F: Fn()>(
+    libraries: Vec<(String, BamStream)>,
     seq_names: &HashSet<Vec<u8>>,
     options: &DepthOptions,
     callback: &Option<F>,
-) {
-    let seq_lengths = seq_lengths_from_header(&bam, seq_names);
-    bed_from_bam(&seq_lengths, bam, options, callback);
+) -> Result<(), error::Error> {
+    let seq_lengths = stream_seq_lengths(&libraries, seq_names)?;
+    let labels: Vec<String> = libraries.iter().map(|(label, _)| label.clone()).collect();
+    write_depth(
+        &seq_lengths,
+        &labels,
+        libraries,
+        || panic!("streamed input cannot be reopened"),
+        |libraries, seq_name, length| stream_coverages(libraries, seq_name, length, options),
+        options,
+        callback,
+    )
 }
 
-pub fn get_depth<F: Fn()>(
-    bam: IndexedReader,
+pub fn get_stream_depth<F: Fn()>(
+    libraries: Vec<(String, BamStream)>,
     seq_names: &HashSet<Vec<u8>>,
     options: &DepthOptions,
     callback: &Option<F>,
-) -> Vec<BinnedCov> {
-    let seq_lengths = seq_lengths_from_header(&bam, seq_names);
-    depth_from_bam(&seq_lengths, bam, options, callback)
+) -> Result<Vec<BinnedCov>, error::Error> {
+    let seq_lengths = stream_seq_lengths(&libraries, seq_names)?;
+    let labels: Vec<String> = libraries.iter().map(|(label, _)| label.clone()).collect();
+    binned_depth(
+        &seq_lengths,
+        &labels,
+        libraries,
+        || panic!("streamed input cannot be reopened"),
+        |libraries, seq_name, length| stream_coverages(libraries, seq_name, length, options),
+        options,
+        callback,
+    )
 }
@@ -0,0 +1,320 @@
+//!
+//! Writes coverage as bedGraph-type bigWig data sections with a chromosome
+//! B+ tree and an R-tree index. No zoom levels are written, which genome
+//! browsers and UCSC/pyBigWig readers accept.
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, File};
+use std::io::{BufWriter, Result, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use indexmap::IndexMap;
+
+const BIGWIG_MAGIC: u32 = 0x888F_FC26;
+const CHROM_TREE_MAGIC: u32 = 0x78CA_8C91;
+const CIR_TREE_MAGIC: u32 = 0x2468_ACE0;
+const VERSION: u16 = 4;
+const HEADER_SIZE: u64 = 64;
+const SUMMARY_SIZE: u64 = 40;
+const ITEMS_PER_SLOT: usize = 1024;
+const BLOCK_SIZE: usize = 256;
+
+/// Location and extent of a compressed data section.
+struct DataBlock {
+    chrom_id: u32,
+    start: u32,
+    end: u32,
+    offset: u64,
+    size: u64,
+}
+
+/// Summary over all values written to the file.
+struct TotalSummary {
+    bases_covered: u64,
+    min_val: f64,
+    max_val: f64,
+    sum_data: f64,
+    sum_squares: f64,
+}
+
+pub struct BigWigWriter {
+    writer: BufWriter<File>,
+    chrom_ids: HashMap<String, u32>,
+    full_data_offset: u64,
+    blocks: Vec<DataBlock>,
+    uncompress_buf_size: usize,
+    summary: TotalSummary,
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+/// Number of nodes at each level of a tree with `count` items, leaf level first.
+fn level_sizes(count: usize, block_size: usize) -> Vec<usize> {
+    let mut sizes = vec![count.div_ceil(block_size).max(1)];
+    while *sizes.last().unwrap() > 1 {
+        let last = *sizes.last().unwrap();
+        sizes.push(last.div_ceil(block_size));
+    }
+    sizes
+}
+
+/// Offsets of the first node in each level, for levels written top down.
+fn level_offsets(sizes: &[usize], node_size: impl Fn(usize) -> usize, start: u64) -> Vec<u64> {
+    let mut offsets = vec![0; sizes.len()];
+    let mut offset = start;
+    for height in (0..sizes.len()).rev() {
+        offsets[height] = offset;
+        offset += (sizes[height] * node_size(height)) as u64;
+    }
+    offsets
+}
+
+fn write_chrom_tree<W: Write>(
+    writer: &mut W,
+    seq_lengths: &IndexMap<String, usize>,
+    offset: u64,
+) -> Result<()> {
+    let mut items: Vec<(&[u8], u32, u32)> = seq_lengths
+        .iter()
+        .enumerate()
+        .map(|(i, (name, length))| (name.as_bytes(), i as u32, *length as u32))
+        .collect();
+    items.sort();
+    let key_size = items
+        .iter()
+        .map(|item| item.0.len())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let block_size = BLOCK_SIZE.min(items.len()).max(1);
+    write_u32(writer, CHROM_TREE_MAGIC)?;
+    write_u32(writer, block_size as u32)?;
+    write_u32(writer, key_size as u32)?;
+    write_u32(writer, 8)?;
+    write_u64(writer, items.len() as u64)?;
+    write_u64(writer, 0)?;
+
+    let item_size = key_size + 8;
+    let node_size = 4 + block_size * item_size;
+    let sizes = level_sizes(items.len(), block_size);
+    let offsets = level_offsets(&sizes, |_| node_size, offset + 32);
+    let write_key = |writer: &mut W, key: &[u8]| -> Result<()> {
+        writer.write_all(key)?;
+        writer.write_all(&vec![0; key_size - key.len()])
+    };
+    for height in (0..sizes.len()).rev() {
+        let span = block_size.pow(height as u32);
+        for node in 0..sizes[height] {
+            let first = node * block_size;
+            let last = if height == 0 {
+                ((node + 1) * block_size).min(items.len())
+            } else {
+                ((node + 1) * block_size).min(sizes[height - 1])
+            };
+            writer.write_all(&[(height == 0) as u8, 0])?;
+            write_u16(writer, (last - first) as u16)?;
+            if height == 0 {
+                for (key, chrom_id, chrom_size) in &items[first..last] {
+                    write_key(writer, key)?;
+                    write_u32(writer, *chrom_id)?;
+                    write_u32(writer, *chrom_size)?;
+                }
+            } else {
+                for child in first..last {
+                    write_key(writer, items[child * span].0)?;
+                    write_u64(writer, offsets[height - 1] + (child * node_size) as u64)?;
+                }
+            }
+            writer.write_all(&vec![0; (block_size - (last - first)) * item_size])?;
+        }
+    }
+    Ok(())
+}
+
+impl BigWigWriter {
+    /// Create a bigWig file for the given sequences. Coverage must then be
+    /// written with `write_runs` in the same order as `seq_lengths`.
+    pub fn create(path: &PathBuf, seq_lengths: &IndexMap<String, usize>) -> Result<BigWigWriter> {
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&[0; (HEADER_SIZE + SUMMARY_SIZE) as usize])?;
+        write_chrom_tree(&mut writer, seq_lengths, HEADER_SIZE + SUMMARY_SIZE)?;
+        let full_data_offset = writer.stream_position()?;
+        write_u64(&mut writer, 0)?;
+        Ok(BigWigWriter {
+            writer,
+            chrom_ids: seq_lengths
+                .keys()
+                .enumerate()
+                .map(|(i, name)| (name.clone(), i as u32))
+                .collect(),
+            full_data_offset,
+            blocks: vec![],
+            uncompress_buf_size: 0,
+            summary: TotalSummary {
+                bases_covered: 0,
+                min_val: f64::MAX,
+                max_val: f64::MIN,
+                sum_data: 0.0,
+                sum_squares: 0.0,
+            },
+        })
+    }
+
+    /// Write (start, end, value) runs for a sequence as bedGraph data sections.
+    pub fn write_runs(&mut self, seq_name: &str, runs: &[(usize, usize, f32)]) -> Result<()> {
+        let chrom_id = self.chrom_ids[seq_name];
+        for chunk in runs.chunks(ITEMS_PER_SLOT) {
+            let start = chunk[0].0 as u32;
+            let end = chunk[chunk.len() - 1].1 as u32;
+            let mut buffer: Vec<u8> = Vec::with_capacity(24 + chunk.len() * 12);
+            write_u32(&mut buffer, chrom_id)?;
+            write_u32(&mut buffer, start)?;
+            write_u32(&mut buffer, end)?;
+            write_u32(&mut buffer, 0)?;
+            write_u32(&mut buffer, 0)?;
+            buffer.write_all(&[1, 0])?;
+            write_u16(&mut buffer, chunk.len() as u16)?;
+            for (run_start, run_end, value) in chunk {
+                write_u32(&mut buffer, *run_start as u32)?;
+                write_u32(&mut buffer, *run_end as u32)?;
+                buffer.write_all(&value.to_le_bytes())?;
+                let span = (run_end - run_start) as f64;
+                let value = *value as f64;
+                self.summary.bases_covered += (run_end - run_start) as u64;
+                self.summary.min_val = self.summary.min_val.min(value);
+                self.summary.max_val = self.summary.max_val.max(value);
+                self.summary.sum_data += value * span;
+                self.summary.sum_squares += value * value * span;
+            }
+            self.uncompress_buf_size = self.uncompress_buf_size.max(buffer.len());
+            let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&buffer)?;
+            let compressed = encoder.finish()?;
+            let offset = self.writer.stream_position()?;
+            self.writer.write_all(&compressed)?;
+            self.blocks.push(DataBlock {
+                chrom_id,
+                start,
+                end,
+                offset,
+                size: compressed.len() as u64,
+            });
+        }
+        Ok(())
+    }
+
+    fn write_index(&mut self, offset: u64) -> Result<()> {
+        let blocks = &self.blocks;
+        let writer = &mut self.writer;
+        let block_size = BLOCK_SIZE.min(blocks.len()).max(1);
+        write_u32(writer, CIR_TREE_MAGIC)?;
+        write_u32(writer, block_size as u32)?;
+        write_u64(writer, blocks.len() as u64)?;
+        match (blocks.first(), blocks.last()) {
+            (Some(first), Some(last)) => {
+                write_u32(writer, first.chrom_id)?;
+                write_u32(writer, first.start)?;
+                write_u32(writer, last.chrom_id)?;
+                write_u32(writer, last.end)?;
+            }
+            _ => writer.write_all(&[0; 16])?,
+        }
+        write_u64(writer, offset)?;
+        write_u32(writer, ITEMS_PER_SLOT as u32)?;
+        write_u32(writer, 0)?;
+
+        let sizes = level_sizes(blocks.len(), block_size);
+        let item_size = |height: usize| if height == 0 { 32 } else { 24 };
+        let node_size = |height: usize| 4 + block_size * item_size(height);
+        let offsets = level_offsets(&sizes, node_size, offset + 48);
+        for height in (0..sizes.len()).rev() {
+            let span = block_size.pow(height as u32);
+            for node in 0..sizes[height] {
+                let first = node * block_size;
+                let last = if height == 0 {
+                    ((node + 1) * block_size).min(blocks.len())
+                } else {
+                    ((node + 1) * block_size).min(sizes[height - 1])
+                };
+                writer.write_all(&[(height == 0) as u8, 0])?;
+                write_u16(writer, (last - first) as u16)?;
+                for i in first..last {
+                    let (start_block, end_block) = if height == 0 {
+                        (&blocks[i], &blocks[i])
+                    } else {
+                        (
+                            &blocks[i * span],
+                            &blocks[((i + 1) * span).min(blocks.len()) - 1],
+                        )
+                    };
+                    write_u32(writer, start_block.chrom_id)?;
+                    write_u32(writer, start_block.start)?;
+                    write_u32(writer, end_block.chrom_id)?;
+                    write_u32(writer, end_block.end)?;
+                    if height == 0 {
+                        write_u64(writer, start_block.offset)?;
+                        write_u64(writer, start_block.size)?;
+                    } else {
+                        write_u64(
+                            writer,
+                            offsets[height - 1] + (i * node_size(height - 1)) as u64,
+                        )?;
+                    }
+                }
+                writer.write_all(&vec![0; (block_size - (last - first)) * item_size(height)])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the index and fill in the header, summary and section count.
+    pub fn finish(mut self) -> Result<()> {
+        let full_index_offset = self.writer.stream_position()?;
+        self.write_index(full_index_offset)?;
+
+        let writer = &mut self.writer;
+        writer.seek(SeekFrom::Start(0))?;
+        write_u32(writer, BIGWIG_MAGIC)?;
+        write_u16(writer, VERSION)?;
+        write_u16(writer, 0)?;
+        write_u64(writer, HEADER_SIZE + SUMMARY_SIZE)?;
+        write_u64(writer, self.full_data_offset)?;
+        write_u64(writer, full_index_offset)?;
+        write_u16(writer, 0)?;
+        write_u16(writer, 0)?;
+        write_u64(writer, 0)?;
+        write_u64(writer, HEADER_SIZE)?;
+        write_u32(writer, self.uncompress_buf_size as u32)?;
+        write_u64(writer, 0)?;
+
+        let summary = &self.summary;
+        write_u64(writer, summary.bases_covered)?;
+        if summary.bases_covered == 0 {
+            writer.write_all(&[0; 32])?;
+        } else {
+            writer.write_all(&summary.min_val.to_le_bytes())?;
+            writer.write_all(&summary.max_val.to_le_bytes())?;
+            writer.write_all(&summary.sum_data.to_le_bytes())?;
+            writer.write_all(&summary.sum_squares.to_le_bytes())?;
+        }
+
+        writer.seek(SeekFrom::Start(self.full_data_offset))?;
+        write_u64(writer, self.blocks.len() as u64)?;
+        writer.flush()
+    }
+}
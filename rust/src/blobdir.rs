@@ -1,15 +1,16 @@
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::fs::{rename, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
 use flate2::read::GzDecoder;
 use glob::glob;
+use regex::Regex;
 use serde;
 use serde::{Deserialize, Serialize};
 use serde_aux::prelude::*;
 use serde_json;
-use serde_with::{serde_as, DefaultOnError};
+use serde_with::{serde_as, skip_serializing_none, DefaultOnError};
 use titlecase::titlecase;
 use url::Url;
 
@@ -26,6 +27,7 @@ fn default_level() -> String {
     "scaffold".to_string()
 }
 
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AssemblyMeta {
     #[serde(default = "default_accession")]
@@ -52,10 +54,12 @@ pub enum Datatype {
     String,
 }
 
+#[skip_serializing_none]
 #[serde_as]
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct FieldMeta {
     pub id: String,
+    pub name: Option<String>,
     #[serde(rename = "type")]
     pub field_type: Option<String>,
     pub scale: Option<String>,
@@ -72,8 +76,12 @@ pub struct FieldMeta {
     pub active: Option<bool>,
     #[serde(rename = "set")]
     pub odb_set: Option<String>,
+    /// Any other properties, kept so they are written back unchanged
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
 }
 
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct PlotMeta {
     pub x: Option<String>,
@@ -82,6 +90,7 @@ pub struct PlotMeta {
     pub cat: Option<String>,
 }
 
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TaxonMeta {
     #[serde(default = "default_taxname")]
@@ -108,6 +117,7 @@ fn default_taxid() -> String {
     "0".to_string()
 }
 
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Meta {
     pub id: String,
@@ -124,8 +134,13 @@ pub struct Meta {
     #[serde(default = "default_plotmeta")]
     pub plot: PlotMeta,
     pub taxon: TaxonMeta,
+    #[serde(skip)]
     pub field_list: Option<HashMap<String, FieldMeta>>,
+    #[serde(skip)]
     pub busco_list: Option<Vec<(String, usize, String)>>,
+    /// Any other properties, kept so they are written back unchanged
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
 }
 
 fn default_revision() -> u8 {
@@ -142,6 +157,7 @@ fn default_plotmeta() -> PlotMeta {
     }
 }
 
+#[skip_serializing_none]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Field<T> {
     // pub meta: FieldMeta,
@@ -163,6 +179,8 @@ pub struct Filter {
     pub max: Option<f64>,
     pub invert: bool,
     pub key: Option<Vec<usize>>,
+    pub keys: Option<Vec<String>>,
+    pub regex: Option<Regex>,
 }
 
 impl Default for Filter {
@@ -172,10 +190,28 @@ impl Default for Filter {
             max: None,
             invert: false,
             key: None,
+            keys: None,
+            regex: None,
         }
     }
 }
 
+impl Filter {
+    /// Combine the parameters of another filter on the same field.
+    fn merge(&mut self, other: Filter) {
+        self.min = other.min.or(self.min);
+        self.max = other.max.or(self.max);
+        self.invert |= other.invert;
+        self.key = other.key.or(self.key.take());
+        self.keys = other.keys.or(self.keys.take());
+        self.regex = other.regex.or(self.regex.take());
+    }
+}
+
+/// Filters to apply to a BlobDir. A record is kept when it passes every
+/// clause, and passes a clause when it passes any of the filters in it.
+pub type FilterClauses = Vec<Vec<(String, Filter)>>;
+
 #[derive(Debug, Clone)]
 pub struct BuscoGene {
     pub id: String,
@@ -205,6 +241,74 @@ pub fn file_reader(dir: &PathBuf, prefix: &str) -> Option<Box<dyn BufRead>> {
     };
 }
 
+/// Write a value as JSON to a temporary file alongside `path`, then move it
+/// into place so readers never see a partly written file.
+fn write_json_atomic<T: Serialize>(
+    path: &Path,
+    value: &T,
+    pretty: bool,
+) -> Result<(), error::Error> {
+    let file_name = path.file_name().unwrap().to_string_lossy();
+    let tmp_path = path.with_file_name(format!(".{}.tmp", file_name));
+    let file = File::create(&tmp_path)?;
+    let mut writer = BufWriter::new(file);
+    if pretty {
+        serde_json::to_writer_pretty(&mut writer, value)?;
+    } else {
+        serde_json::to_writer(&mut writer, value)?;
+    }
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Write a field to `<id>.json` in a BlobDir.
+pub fn write_field<T: Serialize>(
+    blobdir: &Path,
+    id: &str,
+    field: &Field<T>,
+) -> Result<(), error::Error> {
+    write_json_atomic(&blobdir.join(format!("{}.json", id)), field, false)
+}
+
+/// Write `meta.json` in a BlobDir, replacing any existing file atomically.
+pub fn write_meta(blobdir: &Path, meta: &Meta) -> Result<(), error::Error> {
+    write_json_atomic(&blobdir.join("meta.json"), meta, true)
+}
+
+/// Add a field to the `meta.json` field tree, replacing any field with the
+/// same ID. With a `parent`, the field is added to the children of the
+/// top-level field with the parent ID (created from `parent` if missing) and
+/// the parent range is widened to cover all of its children.
+pub fn upsert_field_meta(fields: &mut Vec<FieldMeta>, field: FieldMeta, parent: Option<FieldMeta>) {
+    fn upsert(fields: &mut Vec<FieldMeta>, field: FieldMeta) {
+        match fields.iter_mut().find(|f| f.id == field.id) {
+            Some(existing) => *existing = field,
+            None => fields.push(field),
+        }
+    }
+    let parent = match parent {
+        Some(parent) => parent,
+        None => return upsert(fields, field),
+    };
+    let index = match fields.iter().position(|f| f.id == parent.id) {
+        Some(index) => index,
+        None => {
+            fields.push(parent);
+            fields.len() - 1
+        }
+    };
+    let parent = &mut fields[index];
+    let children = parent.children.get_or_insert_with(Vec::new);
+    upsert(children, field);
+    parent.range = children
+        .iter()
+        .filter_map(|child| child.range)
+        .reduce(|a, b| [a[0].min(b[0]), a[1].max(b[1])])
+        .or(parent.range);
+}
+
 #[derive(Deserialize, Debug, Clone, Serialize)]
 pub struct Keys {
     pub headers: String,
@@ -436,76 +540,123 @@ pub fn parse_field_string(id: String, blobdir: &PathBuf) -> Result<Vec<String>,
 pub fn parse_filters(
     options: &cli::PlotOptions,
     plot_meta: Option<&HashMap<String, String>>,
-) -> HashMap<String, Filter> {
+) -> Result<FilterClauses, error::Error> {
     let mut filters = options.filter.clone();
-    if plot_meta.is_some() && options.x_limit.is_some() {
-        if let Some((min_value, max_value)) = options.x_limit.clone().unwrap().split_once(",") {
-            let x_field = plot_meta.unwrap().get("x").unwrap();
+    for (axis, limit) in [("x", &options.x_limit), ("y", &options.y_limit)] {
+        let (plot_meta, limit) = match (plot_meta, limit) {
+            (Some(plot_meta), Some(limit)) => (plot_meta, limit),
+            _ => continue,
+        };
+        if let Some((min_value, max_value)) = limit.split_once(",") {
+            let field = plot_meta
+                .get(axis)
+                .ok_or_else(|| error::Error::AxisNotDefined(axis.to_string()))?;
             if !min_value.is_empty() {
-                filters.push(format!("{}--Min={}", x_field, min_value))
+                filters.push(format!("{}--Min={}", field, min_value))
             }
             if !max_value.is_empty() {
-                filters.push(format!("{}--Max={}", x_field, max_value))
+                filters.push(format!("{}--Max={}", field, max_value))
             }
         }
     }
-    if plot_meta.is_some() && options.y_limit.is_some() {
-        if let Some((min_value, max_value)) = options.y_limit.clone().unwrap().split_once(",") {
-            let y_field = plot_meta.unwrap().get("y").unwrap();
-            if !min_value.is_empty() {
-                filters.push(format!("{}--Min={}", y_field, min_value))
+    parse_filter_strings(&filters)
+}
+
+fn parse_filter_term(term: &str) -> Result<(String, Filter), error::Error> {
+    let invalid = |message: &str| error::Error::InvalidFilter(format!("{}: {}", term, message));
+    let (negate, filter_term) = match term.trim().strip_prefix('!') {
+        Some(filter_term) => (true, filter_term),
+        None => (false, term.trim()),
+    };
+    let (id, parameters) = filter_term
+        .split_once("--")
+        .ok_or_else(|| invalid("expected <field>--<param>[=<value>]"))?;
+    let mut filter = Filter {
+        invert: negate,
+        ..Default::default()
+    };
+    for parameter in parameters.split("--") {
+        let (param, value) = match parameter.split_once('=') {
+            Some((param, value)) => (param, Some(value)),
+            None => (parameter, None),
+        };
+        match (param, value) {
+            ("Inv", None) => filter.invert = true,
+            ("Max", Some(value)) => {
+                filter.max = Some(value.parse().map_err(|_| invalid("Max is not a number"))?)
             }
-            if !max_value.is_empty() {
-                filters.push(format!("{}--Max={}", y_field, max_value))
+            ("Min", Some(value)) => {
+                filter.min = Some(value.parse().map_err(|_| invalid("Min is not a number"))?)
+            }
+            ("Key", Some(value)) => {
+                filter.key = Some(
+                    value
+                        .split(",")
+                        .map(|x| x.parse::<usize>())
+                        .collect::<Result<Vec<usize>, _>>()
+                        .map_err(|_| invalid("Key is not a list of key indices"))?,
+                )
+            }
+            ("Keys", Some(value)) => {
+                filter.keys = Some(value.split(",").map(String::from).collect())
             }
+            ("Regex", Some(value)) => {
+                filter.regex = Some(Regex::new(value).map_err(|err| invalid(&err.to_string()))?)
+            }
+            _ => return Err(invalid(&format!("unknown parameter {}", parameter))),
         }
     }
-    let mut filter_map = HashMap::new();
+    Ok((id.to_string(), filter))
+}
+
+/// Parse filter strings in the form `<field>--<param>[=<value>]`, e.g.
+/// `length--Min=1000` or `bestsumorder_phylum--Keys=Chordata--Inv`.
+///
+/// Records must match every filter string to be kept. Filters joined by
+/// `||` match records that pass any of them, and `--Inv` or a leading `!`
+/// negates a filter. Separate filter strings on the same field are merged
+/// into one filter, so `--Inv` on either applies to both.
+///
+/// # Examples
+///
+/// ```
+/// # use crate::blobtk::blobdir::parse_filter_strings;
+/// let filters = parse_filter_strings(&[
+///     "length--Min=1000".to_string(),
+///     "length--Max=5000".to_string(),
+///     "bestsumorder_phylum--Keys=Chordata,Arthropoda||!identifiers--Regex=^ctg".to_string(),
+/// ])
+/// .unwrap();
+/// assert_eq!(filters.len(), 2);
+/// assert_eq!(filters[0][0].1.max, Some(5000.0));
+/// assert!(filters[1][1].1.invert);
+/// assert!(parse_filter_strings(&["length--Min=big".to_string()]).is_err());
+/// ```
+pub fn parse_filter_strings(filters: &[String]) -> Result<FilterClauses, error::Error> {
+    let mut clauses: FilterClauses = vec![];
     for filter in filters.iter() {
-        if let Some((id, parameter)) = filter.split_once("--") {
-            if !filter_map.contains_key(id) {
-                filter_map.insert(
-                    id.to_string(),
-                    Filter {
-                        ..Default::default()
-                    },
-                );
-            };
-            let filter_params = filter_map.get_mut(&id.to_string()).unwrap();
-            if parameter == "Inv" {
-                filter_params.invert = true;
+        let mut clause = filter
+            .split("||")
+            .map(parse_filter_term)
+            .collect::<Result<Vec<(String, Filter)>, error::Error>>()?;
+        if clause.len() == 1 {
+            let (id, _) = &clause[0];
+            let existing = clauses
+                .iter_mut()
+                .find(|existing| existing.len() == 1 && &existing[0].0 == id);
+            if let Some(existing) = existing {
+                existing[0].1.merge(clause.remove(0).1);
                 continue;
-            };
-            if let Some((param, value)) = parameter.split_once("=") {
-                match param {
-                    "Max" => filter_params.max = Some(value.parse().unwrap()),
-                    "Min" => filter_params.min = Some(value.parse().unwrap()),
-                    "Key" => {
-                        filter_params.key = Some(
-                            value
-                                .split(",")
-                                .map(|x| x.parse::<usize>().unwrap())
-                                .collect(),
-                        )
-                    }
-                    _ => (),
-                }
             }
-        };
+        }
+        clauses.push(clause);
     }
-
-    filter_map
+    Ok(clauses)
 }
 
-// TODO: add filters for int and cat values
-pub fn filter_float_values(values: Vec<f64>, filter: Filter, indices: Vec<usize>) -> Vec<usize> {
-    let initial: Vec<usize> = if indices.is_empty() {
-        (0..(values.len() - 1)).collect()
-    } else {
-        indices.clone()
-    };
+pub fn filter_float_values(values: Vec<f64>, filter: &Filter, indices: Vec<usize>) -> Vec<usize> {
     let mut output = vec![];
-    for i in initial {
+    for i in indices {
         let mut keep = true;
         if filter.max.is_some() {
             if values[i] > filter.max.unwrap() {
@@ -527,14 +678,9 @@ pub fn filter_float_values(values: Vec<f64>, filter: Filter, indices: Vec<usize>
     output
 }
 
-pub fn filter_int_values(values: Vec<usize>, filter: Filter, indices: Vec<usize>) -> Vec<usize> {
-    let initial: Vec<usize> = if indices.is_empty() {
-        (0..(values.len() - 1)).collect()
-    } else {
-        indices.clone()
-    };
+pub fn filter_int_values(values: Vec<usize>, filter: &Filter, indices: Vec<usize>) -> Vec<usize> {
     let mut output = vec![];
-    for i in initial {
+    for i in indices {
         let mut keep = true;
         if filter.max.is_some() {
             if values[i] as f64 > filter.max.unwrap() {
@@ -556,34 +702,177 @@ pub fn filter_int_values(values: Vec<usize>, filter: Filter, indices: Vec<usize>
     output
 }
 
-pub fn set_filters(filters: HashMap<String, Filter>, meta: &Meta, blobdir: &PathBuf) -> Vec<usize> {
-    let mut indices = vec![];
-    let field_list = meta.field_list.clone().unwrap();
-    for (id, filter) in filters {
-        let field_meta_option = field_list.get(&id);
-        match field_meta_option {
-            Some(field_meta) => {
-                let field = field_meta.clone();
-                match field.datatype {
-                    Some(Datatype::Float) => {
-                        let values = parse_field_float(field_meta.id.clone(), blobdir).unwrap();
-                        indices = filter_float_values(values, filter, indices);
-                    }
-                    Some(Datatype::Integer) => {
-                        let values = parse_field_int(field_meta.id.clone(), blobdir).unwrap();
-                        indices = filter_int_values(values, filter, indices);
-                    }
-                    Some(_) => (),
-                    None => (),
-                }
+/// Keep string values listed in `Keys` or matching `Regex`.
+pub fn filter_string_values(
+    values: Vec<String>,
+    filter: &Filter,
+    indices: Vec<usize>,
+) -> Vec<usize> {
+    let mut output = vec![];
+    for i in indices {
+        let mut keep = true;
+        if let Some(names) = &filter.keys {
+            if !names.contains(&values[i]) {
+                keep = false;
             }
-            None => (),
-        };
+        }
+        if let Some(regex) = &filter.regex {
+            if !regex.is_match(&values[i]) {
+                keep = false;
+            }
+        }
+        if filter.invert {
+            keep = !keep;
+        }
+        if keep {
+            output.push(i);
+        }
+    }
+    output
+}
+
+/// Keep category values whose key index is listed in `Key`, whose
+/// name is listed in `Keys` or whose name matches `Regex`.
+pub fn filter_cat_values(
+    values: Vec<(String, usize)>,
+    filter: &Filter,
+    indices: Vec<usize>,
+) -> Vec<usize> {
+    let mut output = vec![];
+    for i in indices {
+        let (name, key) = &values[i];
+        let mut keep = true;
+        if let Some(key_indices) = &filter.key {
+            if !key_indices.contains(key) {
+                keep = false;
+            }
+        }
+        if let Some(key_names) = &filter.keys {
+            if !key_names.contains(name) {
+                keep = false;
+            }
+        }
+        if let Some(regex) = &filter.regex {
+            if !regex.is_match(name) {
+                keep = false;
+            }
+        }
+        if filter.invert {
+            keep = !keep;
+        }
+        if keep {
+            output.push(i);
+        }
     }
-    if indices.is_empty() {
-        indices = (0..meta.records).collect();
+    output
+}
+
+fn filter_field(
+    id: &str,
+    filter: &Filter,
+    field_list: &HashMap<String, FieldMeta>,
+    blobdir: &PathBuf,
+    indices: Vec<usize>,
+) -> Result<Vec<usize>, error::Error> {
+    let field_meta = match field_list.get(id) {
+        Some(field_meta) => field_meta,
+        None => {
+            return Err(error::Error::InvalidFilter(format!(
+                "{} is not a field in {}",
+                id,
+                blobdir.display()
+            )))
+        }
+    };
+    match (field_meta.field_type.as_deref(), &field_meta.datatype) {
+        (Some("category"), _) => {
+            let values = parse_field_cat(id.to_string(), blobdir)?;
+            Ok(filter_cat_values(values, filter, indices))
+        }
+        (Some("identifier"), _) | (_, Some(Datatype::String)) => {
+            let values = parse_field_string(id.to_string(), blobdir)?;
+            Ok(filter_string_values(values, filter, indices))
+        }
+        (_, Some(Datatype::Float)) => {
+            let values = parse_field_float(id.to_string(), blobdir)?;
+            Ok(filter_float_values(values, filter, indices))
+        }
+        (_, Some(Datatype::Integer)) => {
+            let values = parse_field_int(id.to_string(), blobdir)?;
+            Ok(filter_int_values(values, filter, indices))
+        }
+        _ => Err(error::Error::InvalidFilter(format!(
+            "unable to filter on field {}",
+            id
+        ))),
+    }
+}
+
+/// Indices of the records that pass every clause in a set of filters.
+pub fn set_filters(
+    filters: FilterClauses,
+    meta: &Meta,
+    blobdir: &PathBuf,
+) -> Result<Vec<usize>, error::Error> {
+    let mut indices: Vec<usize> = (0..meta.records).collect();
+    let field_list = match &meta.field_list {
+        Some(field_list) => field_list,
+        None => return Err(error::Error::NotDefined("field_list".to_string())),
+    };
+    for clause in filters.iter() {
+        let mut kept: HashSet<usize> = HashSet::new();
+        for (id, filter) in clause {
+            kept.extend(filter_field(
+                id,
+                filter,
+                field_list,
+                blobdir,
+                indices.clone(),
+            )?);
+        }
+        indices.retain(|i| kept.contains(i));
+    }
+    Ok(indices)
+}
+
+/// Identifiers of the records in a BlobDir that pass a set of filter strings.
+pub fn filtered_identifiers(
+    blobdir: &PathBuf,
+    filters: &[String],
+) -> Result<HashSet<Vec<u8>>, error::Error> {
+    let meta = parse_blobdir(blobdir)?;
+    let indices = set_filters(parse_filter_strings(filters)?, &meta, blobdir)?;
+    let identifiers = parse_field_string("identifiers".to_string(), blobdir)?;
+    Ok(indices
+        .iter()
+        .map(|i| identifiers[*i].as_bytes().to_vec())
+        .collect())
+}
+
+/// Map the identifiers of filtered records to bins named by the keys of a
+/// category field. Bins are returned in the order first seen.
+pub fn category_bins(
+    blobdir: &PathBuf,
+    field: &str,
+    filters: &[String],
+) -> Result<(Vec<String>, HashMap<Vec<u8>, usize>), error::Error> {
+    let meta = parse_blobdir(blobdir)?;
+    let indices = set_filters(parse_filter_strings(filters)?, &meta, blobdir)?;
+    let identifiers = parse_field_string("identifiers".to_string(), blobdir)?;
+    let values = parse_field_cat(field.to_string(), blobdir)?;
+    let mut bins: Vec<String> = vec![];
+    let mut bin_indices: HashMap<usize, usize> = HashMap::new();
+    let mut seq_bins = HashMap::new();
+    for i in indices {
+        let (key, key_index) = &values[i];
+        let next_index = bins.len();
+        let index = *bin_indices.entry(*key_index).or_insert_with(|| {
+            bins.push(key.clone());
+            next_index
+        });
+        seq_bins.insert(identifiers[i].as_bytes().to_vec(), index);
     }
-    indices
+    Ok((bins, seq_bins))
 }
 
 pub fn apply_filter_float(values: &Vec<f64>, indices: &Vec<usize>) -> Vec<f64> {
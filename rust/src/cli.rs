@@ -1,10 +1,11 @@
 use std::collections::HashSet;
+use std::fmt;
 use std::path::PathBuf;
 // use std::str::FromStr;
 // use std::string::ParseError;
 
-use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
-use clap_num::number_range;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use clap_num::{maybe_hex, number_range};
 use pyo3::pyclass;
 use serde;
 use serde::{Deserialize, Serialize};
@@ -13,28 +14,29 @@ use crate::plot::axis::Scale;
 use crate::plot::data::Reducer;
 use crate::plot::ShowLegend;
 
-// fn float_range(s: &str, min: f64, max: f64) -> Result<f64, String> {
-//     debug_assert!(min <= max, "minimum of {} exceeds maximum of {}", min, max);
-//     let val = match s.parse::<f64>() {
-//         Ok(v) => v,
-//         Err(e) => panic!("{:?}", e),
-//     };
-//     check_float_range(val, min, max)
-// }
-
-// fn check_float_range(val: f64, min: f64, max: f64) -> Result<f64, String> {
-//     if val > max {
-//         Err(format!("exceeds maximum of {}", max))
-//     } else if val < min {
-//         Err(format!("less than minimum of {}", min))
-//     } else {
-//         Ok(val)
-//     }
-// }
-
-// fn window_size_range(s: &str) -> Result<f64, String> {
-//     float_range(s, 0.0001, 1000000000.0)
-// }
+fn float_range(s: &str, min: f64, max: f64) -> Result<f64, String> {
+    debug_assert!(min <= max, "minimum of {} exceeds maximum of {}", min, max);
+    let val = s.parse::<f64>().map_err(|e| e.to_string())?;
+    check_float_range(val, min, max)
+}
+
+fn check_float_range(val: f64, min: f64, max: f64) -> Result<f64, String> {
+    if val > max {
+        Err(format!("exceeds maximum of {}", max))
+    } else if val < min {
+        Err(format!("less than minimum of {}", min))
+    } else {
+        Ok(val)
+    }
+}
+
+fn window_size_range(s: &str) -> Result<f64, String> {
+    let val = float_range(s, 0.0001, 1000000000.0)?;
+    if val > 1.0 && val.fract() != 0.0 {
+        return Err("window sizes above 1 must be a whole number of bases".to_string());
+    }
+    Ok(val)
+}
 
 fn bin_size_parser(s: &str) -> Result<usize, String> {
     let mut val = match s.parse::<usize>() {
@@ -53,11 +55,43 @@ fn bin_size_parser(s: &str) -> Result<usize, String> {
 pub struct Arguments {
     #[clap(subcommand)]
     pub cmd: SubCommand,
+    #[command(flatten)]
+    pub compression: CompressionOptions,
+}
+
+fn compression_level_range(s: &str) -> Result<u32, String> {
+    Ok(number_range(s, 0, 9)? as u32)
+}
+
+/// Options for block-gzipped (BGZF) output, used by all `.gz` output files
+#[derive(Args, Clone, Copy, Debug)]
+pub struct CompressionOptions {
+    /// Compression level for .gz output files (0-9, 0 stores uncompressed BGZF blocks)
+    #[arg(long = "compression-level", global = true, default_value_t = 6, value_parser = compression_level_range)]
+    pub compression_level: u32,
+    /// Number of threads to use to compress .gz output files
+    #[arg(long = "compression-threads", global = true, default_value_t = 1)]
+    pub compression_threads: usize,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> CompressionOptions {
+        CompressionOptions {
+            compression_level: 6,
+            compression_threads: 1,
+        }
+    }
 }
 
 /// `blobtk` subcommands
 #[derive(Subcommand, Debug)]
 pub enum SubCommand {
+    /// Add fields to a BlobDir.
+    /// Called as `blobtk add`
+    Add(AddOptions),
+    /// Create a new BlobDir from an assembly FASTA file.
+    /// Called as `blobtk create`
+    Create(CreateOptions),
     /// Calculate sequencing coverage depth.
     /// Called as `blobtk depth`
     Depth(DepthOptions),
@@ -72,12 +106,166 @@ pub enum SubCommand {
     Taxonomy(TaxonomyOptions),
 }
 
+/// How to treat supplementary alignments
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Supplementary {
+    #[default]
+    Include,
+    Exclude,
+}
+
+/// Rule for assigning taxa to sequences in `blobtk add`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum TaxRule {
+    /// Sum bitscores across all hits files
+    #[value(name = "bestsum")]
+    BestSum,
+    /// Sum bitscores from the first hits file (in the order given) with hits to each sequence
+    #[default]
+    #[value(name = "bestsumorder")]
+    BestSumOrder,
+}
+
+impl fmt::Display for TaxRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// How to select read pairs in `blobtk filter`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[pyclass]
+pub enum PairPolicy {
+    /// Keep a pair when either mate aligns to a listed sequence
+    #[default]
+    Either,
+    /// Keep a pair only when both mates align to listed sequences
+    Both,
+    /// Keep a pair unless a mate aligns to an unlisted sequence, so pairs with
+    /// one unmapped mate are kept but pairs split across sequences are not
+    BothOrUnmapped,
+}
+
+impl fmt::Display for PairPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => write!(f, "{}", value.get_name()),
+            None => write!(f, "{:?}", self),
+        }
+    }
+}
+
+/// Read filters shared by `blobtk depth` and `blobtk filter`
+#[derive(Args, Clone, Debug, PartialEq)]
+#[pyclass]
+pub struct ReadFilter {
+    /// Only use reads with all of these flags set
+    #[arg(long = "include-flags", value_name = "FLAGS", default_value_t = 0, value_parser = maybe_hex::<u16>)]
+    pub include_flags: u16,
+    /// Skip reads with any of these flags set (default: UNMAP,SECONDARY,QCFAIL,DUP).
+    /// `blobtk depth --engine pileup` always skips the default flags
+    #[arg(long = "exclude-flags", value_name = "FLAGS", default_value_t = 1796, value_parser = maybe_hex::<u16>)]
+    pub exclude_flags: u16,
+    /// Minimum mapping quality
+    #[arg(long = "min-mapq", default_value_t = 0)]
+    pub min_mapq: u8,
+    /// Minimum number of aligned (M/=/X) bases, or alignment block length
+    /// (PAF column 11, including gaps) for PAF input
+    #[arg(long = "min-aligned-length", default_value_t = 0)]
+    pub min_aligned_length: usize,
+    /// Minimum alignment identity, calculated from the NM tag (0-1)
+    #[arg(long = "min-identity", default_value_t = 0.0)]
+    pub min_identity: f64,
+    /// Include or exclude supplementary alignments
+    #[arg(long, value_enum, default_value_t = Supplementary::Include)]
+    pub supplementary: Supplementary,
+}
+
+impl Default for ReadFilter {
+    fn default() -> ReadFilter {
+        ReadFilter {
+            include_flags: 0,
+            exclude_flags: 1796,
+            min_mapq: 0,
+            min_aligned_length: 0,
+            min_identity: 0.0,
+            supplementary: Supplementary::Include,
+        }
+    }
+}
+
+/// Coverage engine used by `blobtk depth`
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+#[pyclass]
+pub enum DepthEngine {
+    /// Per-base pileup, with no maximum depth
+    #[default]
+    Pileup,
+    /// Single pass over each read's CIGAR string (faster for long reads and high depth)
+    Cigar,
+}
+
+/// Options to pass to `blobtk add`
+#[derive(Parser, Debug)]
+pub struct AddOptions {
+    /// Path to BlobDir directory to add fields to
+    #[arg(long, short = 'd')]
+    pub blobdir: PathBuf,
+    /// Path to `blobtk depth` BED file(s) to add as <library>_cov fields
+    #[arg(long, value_name = "BED", num_args(1..), action = clap::ArgAction::Append)]
+    pub cov: Vec<PathBuf>,
+    /// Path to TSV file(s) with a header row and sequence IDs in the first
+    /// column, to add each other column as an integer, float or category field
+    #[arg(long, value_name = "TSV", num_args(1..), action = clap::ArgAction::Append)]
+    pub text: Vec<PathBuf>,
+    /// Path to BUSCO v5 full_table.tsv file(s) to add as <lineage>_busco fields
+    #[arg(long, value_name = "TSV", num_args(1..), action = clap::ArgAction::Append)]
+    pub busco: Vec<PathBuf>,
+    /// Path to BLAST/DIAMOND hits file(s) in order of priority, with columns
+    /// qseqid staxids bitscore, optionally followed by the BLAST std columns
+    #[arg(long, value_name = "TSV", num_args(1..), action = clap::ArgAction::Append, requires = "taxdump")]
+    pub hits: Vec<PathBuf>,
+    /// Path to NCBI taxdump directory used to assign taxa from --hits
+    #[arg(long, value_name = "DIR", requires = "hits")]
+    pub taxdump: Option<PathBuf>,
+    /// Rule for assigning taxa to sequences from --hits
+    #[arg(long, value_enum, default_value_t = TaxRule::BestSumOrder)]
+    pub taxrule: TaxRule,
+    /// Replace existing fields with the same ID
+    #[arg(long, default_value_t = false)]
+    pub replace: bool,
+}
+
+/// Options to pass to `blobtk create`
+#[derive(Parser, Debug)]
+pub struct CreateOptions {
+    /// Path to assembly FASTA input file
+    #[arg(long, short = 'a')]
+    pub fasta: PathBuf,
+    /// Path to BlobDir directory to create
+    #[arg(long, short = 'd')]
+    pub blobdir: PathBuf,
+    /// Dataset ID (defaults to the BlobDir directory name)
+    #[arg(long)]
+    pub id: Option<String>,
+    /// Assembly level (e.g. contig, scaffold or chromosome)
+    #[arg(long, default_value_t = String::from("scaffold"))]
+    pub level: String,
+    /// Replace an existing BlobDir, removing all of its fields
+    #[arg(long, default_value_t = false)]
+    pub replace: bool,
+}
+
 /// Options to pass to `blobtk depth`
 #[derive(Parser, Debug)]
 #[command(group(
     ArgGroup::new("alignment")
         .required(false)
-        .args(["bam", "cram"]),
+        .multiple(true)
+        .args(["bam", "cram", "paf"]),
 ))]
 #[pyclass]
 pub struct DepthOptions {
@@ -87,24 +275,71 @@ pub struct DepthOptions {
     /// Path to input file containing a list of sequence IDs
     #[arg(long = "list", short = 'i', value_name = "TXT")]
     pub list_file: Option<PathBuf>,
-    /// Path to BAM file
-    #[arg(long, short = 'b')]
-    pub bam: Option<PathBuf>,
-    /// Path to CRAM file
-    #[arg(long, short = 'c')]
-    pub cram: Option<PathBuf>,
+    /// Path to BAM file(s), one per read library
+    #[arg(long, short = 'b', num_args(1..), action = clap::ArgAction::Append)]
+    pub bam: Vec<PathBuf>,
+    /// Path to CRAM file(s), one per read library
+    #[arg(long, short = 'c', num_args(1..), action = clap::ArgAction::Append)]
+    pub cram: Vec<PathBuf>,
+    /// Path to PAF file(s), plain or gzipped, one per read library.
+    /// Sequence lengths are taken from the PAF target columns, so listed
+    /// sequences with no PAF lines are left out of the output
+    #[arg(long, num_args(1..), action = clap::ArgAction::Append)]
+    pub paf: Vec<PathBuf>,
+    /// Read coordinate-sorted BAM/CRAM input (or STDIN with `-`) sequentially, without an index.
+    /// Uses the CIGAR engine, as pileups need an index
+    #[arg(long, default_value_t = false)]
+    pub stream: bool,
+    /// Directory to read and write BAM/CRAM indexes (defaults to alongside the input)
+    #[arg(long = "index-dir", value_name = "DIR")]
+    pub index_dir: Option<PathBuf>,
+    /// Label for each BAM/CRAM/PAF library (defaults to file stem). Labels are
+    /// assigned to all --bam files first, then all --cram files
+    #[arg(long, short = 'l', num_args(1..), action = clap::ArgAction::Append)]
+    pub label: Vec<String>,
     /// Path to assembly FASTA input file (required for CRAM)
     #[arg(long, short = 'a')]
     pub fasta: Option<PathBuf>,
     /// Bin size for coverage calculations (use 0 for full contig length)
     #[arg(long = "bin-size", short = 's', default_value_t = 0, value_parser = bin_size_parser)]
     pub bin_size: usize,
-    // /// Window size for coverage calculations size
-    // #[arg(long = "window-size", short = 'w', num_args(1..), default_values_t = [1.0], value_parser = window_size_range, action = clap::ArgAction::Append)]
-    // pub window_size: Vec<f64>,
-    /// Output bed file name
+    /// Window size(s) for coverage calculations, as a fraction of sequence length (<= 1)
+    /// or a number of bases (> 1). Overrides --bin-size
+    #[arg(long = "window-size", short = 'w', num_args(1..), value_parser = window_size_range, action = clap::ArgAction::Append)]
+    pub window_size: Vec<f64>,
+    /// Step between window starts, as a fraction of window size (<= 1) or a number of bases (> 1).
+    /// Defaults to the window size (non-overlapping windows)
+    #[arg(long = "window-step", value_parser = window_size_range)]
+    pub window_step: Option<f64>,
+    /// Flag to calculate median, standard deviation, covered fractions and read counts
+    #[arg(long, default_value_t = false)]
+    pub stats: bool,
+    /// Depth thresholds for covered fractions (with --stats)
+    #[arg(long, num_args(1..), default_values_t = [1, 5, 10], action = clap::ArgAction::Append)]
+    pub thresholds: Vec<u32>,
+    /// Number of threads to use for per-sequence coverage calculations
+    #[arg(long, short = 't', default_value_t = 1)]
+    pub threads: usize,
+    /// Engine to use for coverage calculations (defaults to cigar with --stream)
+    #[arg(long, value_enum, default_value_t = DepthEngine::Pileup, default_value_if("stream", "true", Some("cigar")))]
+    pub engine: DepthEngine,
+    /// Output bed file name (suffixed with the window size when several are given)
     #[arg(long = "bed", short = 'O', value_name = "BED")]
     pub bed: Option<PathBuf>,
+    /// Output per-base depth as a run-length merged bedGraph file
+    #[arg(long = "bedgraph", value_name = "BEDGRAPH")]
+    pub bedgraph: Option<PathBuf>,
+    /// Output per-base depth as a bigWig file
+    #[arg(long = "bigwig", value_name = "BIGWIG")]
+    pub bigwig: Option<PathBuf>,
+    /// Output depth classes (see --quantize) as a bed file of merged regions
+    #[arg(long = "quantized", value_name = "BED")]
+    pub quantized: Option<PathBuf>,
+    /// Depth class boundaries for quantized output (<min>:<break>:...:[<max>])
+    #[arg(long = "quantize", default_value_t = String::from("0:1:5:150:"))]
+    pub quantize: String,
+    #[command(flatten)]
+    pub read_filter: ReadFilter,
 }
 
 /// Options to pass to `blobtk filter`
@@ -114,24 +349,69 @@ pub struct DepthOptions {
         .required(false)
         .args(["bam", "cram"]),
 ))]
+#[command(group(
+    ArgGroup::new("reads")
+        .required(false)
+        .args(["fastq1", "ubam"]),
+))]
 #[pyclass]
 pub struct FilterOptions {
-    // TODO: add option to invert list (use BAM header)
     /// List of sequence IDs
     #[clap(skip)]
     pub list: Option<HashSet<Vec<u8>>>,
     /// Path to input file containing a list of sequence IDs
     #[arg(long = "list", short = 'i', value_name = "TXT")]
     pub list_file: Option<PathBuf>,
+    /// Path to BlobDir directory to take the list of sequence IDs from
+    #[arg(long, short = 'd', conflicts_with = "list_file")]
+    pub blobdir: Option<PathBuf>,
+    /// BlobDir field filters (e.g. length--Min=1000 or bestsumorder_phylum--Keys=Chordata--Inv).
+    /// Join filters with || to keep records passing either, or use identifiers--Regex=<pattern>
+    #[arg(long, requires = "blobdir", num_args(1..), action = clap::ArgAction::Append)]
+    pub filter: Vec<String>,
+    /// Path to a two-column TSV assigning sequence IDs to bins, to write one
+    /// set of output files per bin (plus unassigned)
+    #[arg(long, value_name = "TSV", conflicts_with_all = ["list_file", "blobdir", "invert", "bam_out"])]
+    pub partition: Option<PathBuf>,
+    /// BlobDir category field to write one set of output files per category
+    #[arg(
+        long = "partition-field",
+        value_name = "FIELD",
+        requires = "blobdir",
+        conflicts_with_all = ["partition", "invert", "bam_out"]
+    )]
+    pub partition_field: Option<String>,
     /// Path to BAM file
     #[arg(long, short = 'b')]
     pub bam: Option<PathBuf>,
     /// Path to CRAM file
     #[arg(long, short = 'c', requires = "fasta")]
     pub cram: Option<PathBuf>,
+    /// Policy for selecting read pairs by where each mate aligns
+    /// (when partitioning, sequences assigned to any bin count as listed)
+    #[arg(long, value_enum, default_value_t = PairPolicy::Either)]
+    pub pairs: PairPolicy,
+    /// Invert the list to keep reads that do not align to the listed sequences,
+    /// including unmapped reads, and sequences from the BAM header that are not listed
+    #[arg(long, default_value_t = false, requires = "alignment")]
+    pub invert: bool,
+    /// Read BAM/CRAM input (or STDIN with `-`) sequentially, without an index.
+    /// Input may be unsorted or sorted by read name
+    #[arg(long, default_value_t = false)]
+    pub stream: bool,
+    /// Directory to read and write BAM/CRAM indexes (defaults to alongside the input)
+    #[arg(long = "index-dir", value_name = "DIR")]
+    pub index_dir: Option<PathBuf>,
     /// Path to assembly FASTA input file (required for CRAM)
     #[arg(long, short = 'a')]
     pub fasta: Option<PathBuf>,
+    /// Sequence region to write to the filtered FASTA file, as name:start-end
+    /// with 1-based inclusive coordinates (needs an indexable FASTA file)
+    #[arg(long, value_name = "REGION", requires = "fasta_out", num_args(1..), action = clap::ArgAction::Append)]
+    pub region: Vec<String>,
+    /// Reverse complement sequences written to the filtered FASTA file
+    #[arg(long, default_value_t = false, requires = "fasta_out")]
+    pub revcomp: bool,
     /// Path to FASTQ file to filter (forward or single reads)
     #[arg(long = "fastq", short = 'f', value_name = "FASTQ")]
     pub fastq1: Option<PathBuf>,
@@ -143,6 +423,27 @@ pub struct FilterOptions {
         requires = "fastq1"
     )]
     pub fastq2: Option<PathBuf>,
+    /// Read pairs from a single interleaved FASTQ file (filtered output is also interleaved)
+    #[arg(
+        long,
+        requires = "fastq1",
+        conflicts_with = "fastq2",
+        default_value_t = false
+    )]
+    pub interleaved: bool,
+    /// Write paired reads to a single interleaved FASTQ file
+    #[arg(long = "interleaved-out", requires = "fastq1", default_value_t = false)]
+    pub interleaved_out: bool,
+    /// Buffer mates that are not in the same order in both FASTQ files (or
+    /// adjacent in an interleaved file) until they can be paired
+    #[arg(long, requires = "fastq1", default_value_t = false)]
+    pub repair: bool,
+    /// Path to unaligned BAM file to filter (e.g. PacBio HiFi reads)
+    #[arg(long, value_name = "BAM", conflicts_with_all = ["fastq1", "partition", "partition_field"])]
+    pub ubam: Option<PathBuf>,
+    /// Flag to output a filtered unaligned BAM file, keeping all tags
+    #[arg(long = "ubam-out", requires = "ubam", default_value_t = false)]
+    pub ubam_out: bool,
     /// Suffix to use for output filtered files
     #[arg(long, short = 'S', value_name = "SUFFIX", default_value_t = String::from("filtered"))]
     pub suffix: String,
@@ -154,17 +455,26 @@ pub struct FilterOptions {
         default_value_t = false
     )]
     pub fasta_out: bool,
-    /// Flag to output filtered FASTQ files
+    /// Flag to output filtered FASTQ files (converted from --ubam if set)
     #[arg(
         long = "fastq-out",
         short = 'F',
-        requires = "fastq1",
+        requires = "reads",
         default_value_t = false
     )]
     pub fastq_out: bool,
     /// Path to output list of read IDs
     #[arg(long = "read-list", short = 'O', value_name = "TXT")]
     pub read_list: Option<PathBuf>,
+    /// Path to output BAM/CRAM file of alignments on the kept sequences
+    /// (CRAM when the name ends in .cram, using the --fasta reference)
+    #[arg(long = "bam-out", value_name = "BAM", requires = "alignment")]
+    pub bam_out: Option<PathBuf>,
+    /// Path to write a JSON report of the run
+    #[arg(long, value_name = "JSON")]
+    pub report: Option<PathBuf>,
+    #[command(flatten)]
+    pub read_filter: ReadFilter,
 }
 
 #[derive(ValueEnum, Clone, Debug, Default)]
@@ -208,6 +518,7 @@ pub struct PlotOptions {
     /// Output filename
     #[arg(long, short = 'o', default_value_t = String::from("output.svg"))]
     pub output: String,
+    /// BlobDir field filters (e.g. length--Min=1000 or bestsumorder_phylum--Keys=Chordata--Inv)
     #[arg(long, short = 'f')]
     pub filter: Vec<String>,
     /// Segment count for snail plot
@@ -0,0 +1,250 @@
+//!
+//! Invoked by calling:
+//! `blobtk create <args>`
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all, read_dir, remove_file};
+use std::path::Path;
+
+use anyhow;
+use needletail::parse_fastx_file;
+
+use crate::blobdir::{self, AssemblyMeta, Datatype, Field, FieldMeta, Meta, PlotMeta, TaxonMeta};
+use crate::cli;
+use crate::error;
+use crate::utils::styled_progress_bar;
+
+pub use cli::CreateOptions;
+
+/// Base composition of a single sequence.
+#[derive(Debug, Default)]
+pub struct SeqStats {
+    pub length: usize,
+    pub ncount: usize,
+    /// Proportion of G and C among unambiguous A, C, G and T bases
+    pub gc: f64,
+}
+
+impl SeqStats {
+    pub fn new(seq: &[u8]) -> SeqStats {
+        let mut gc_count = 0;
+        let mut at_count = 0;
+        let mut ncount = 0;
+        for base in seq {
+            match base {
+                b'G' | b'C' | b'g' | b'c' => gc_count += 1,
+                b'A' | b'T' | b'a' | b't' => at_count += 1,
+                b'N' | b'n' => ncount += 1,
+                _ => (),
+            }
+        }
+        let gc = if gc_count + at_count == 0 {
+            0.0
+        } else {
+            gc_count as f64 / (gc_count + at_count) as f64
+        };
+        SeqStats {
+            length: seq.len(),
+            ncount,
+            gc: (gc * 10000.0).round() / 10000.0,
+        }
+    }
+}
+
+fn min_max(values: &[f64]) -> [f64; 2] {
+    if values.is_empty() {
+        return [0.0, 0.0];
+    }
+    values
+        .iter()
+        .fold([f64::INFINITY, f64::NEG_INFINITY], |[min, max], value| {
+            [min.min(*value), max.max(*value)]
+        })
+}
+
+/// Metadata for a variable field plotted on a linear or log scale.
+pub fn variable_field_meta(
+    id: &str,
+    name: &str,
+    datatype: Datatype,
+    scale: &str,
+    range: [f64; 2],
+) -> FieldMeta {
+    let clamp = if scale == "scaleLog" && range[0] <= 0.0 {
+        Some(1.0)
+    } else {
+        None
+    };
+    FieldMeta {
+        id: id.to_string(),
+        name: Some(name.to_string()),
+        field_type: Some("variable".to_string()),
+        scale: Some(scale.to_string()),
+        datatype: Some(datatype),
+        range: Some(range),
+        clamp,
+        ..Default::default()
+    }
+}
+
+fn values_field<T>(values: Vec<T>) -> Field<T> {
+    Field {
+        values,
+        keys: vec![],
+        category_slot: None,
+        headers: None,
+    }
+}
+
+/// Remove the meta and field JSON files of an existing BlobDir so no fields
+/// from the old dataset are left behind when it is replaced.
+fn clear_blobdir(blobdir: &Path) -> Result<(), error::Error> {
+    for entry in read_dir(blobdir)? {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_file() && (file_name.ends_with(".json") || file_name.ends_with(".json.gz")) {
+            remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Execute the `create` subcommand from `blobtk`. Read an assembly FASTA
+/// file once and write a BlobDir with identifiers, GC, length and N count.
+pub fn create(options: &cli::CreateOptions) -> Result<(), anyhow::Error> {
+    // open the FASTA file first so a missing file leaves an existing BlobDir alone
+    let mut reader = parse_fastx_file(&options.fasta).map_err(|err| {
+        error::Error::FileNotFound(format!("{}: {}", options.fasta.display(), err))
+    })?;
+    if blobdir::get_path(&options.blobdir, "meta.json").is_some() {
+        if !options.replace {
+            return Err(error::Error::InvalidOption(format!(
+                "BlobDir {} already exists, use --replace to overwrite",
+                options.blobdir.display()
+            ))
+            .into());
+        }
+        clear_blobdir(&options.blobdir)?;
+    }
+    create_dir_all(&options.blobdir)?;
+
+    let mut identifiers: Vec<String> = vec![];
+    let mut gc_values: Vec<f64> = vec![];
+    let mut length_values: Vec<usize> = vec![];
+    let mut ncount_values: Vec<usize> = vec![];
+    let progress_bar = styled_progress_bar(0, "Reading FASTA");
+    while let Some(record) = reader.next() {
+        let seqrec = record.map_err(|err| {
+            error::Error::ParseError(format!("{}: {}", options.fasta.display(), err))
+        })?;
+        let id = String::from_utf8_lossy(seqrec.id());
+        identifiers.push(id.split_whitespace().next().unwrap_or("").to_string());
+        let stats = SeqStats::new(&seqrec.seq());
+        gc_values.push(stats.gc);
+        length_values.push(stats.length);
+        ncount_values.push(stats.ncount);
+        progress_bar.inc(1);
+    }
+    progress_bar.finish();
+
+    let length_range = min_max(
+        &length_values
+            .iter()
+            .map(|length| *length as f64)
+            .collect::<Vec<f64>>(),
+    );
+    let ncount_range = min_max(
+        &ncount_values
+            .iter()
+            .map(|ncount| *ncount as f64)
+            .collect::<Vec<f64>>(),
+    );
+    let identifiers_meta = FieldMeta {
+        id: "identifiers".to_string(),
+        field_type: Some("identifier".to_string()),
+        ..Default::default()
+    };
+    let mut gc_meta = variable_field_meta(
+        "gc",
+        "GC",
+        Datatype::Float,
+        "scaleLinear",
+        min_max(&gc_values),
+    );
+    gc_meta.preload = Some(true);
+    let mut length_meta = variable_field_meta(
+        "length",
+        "Length",
+        Datatype::Integer,
+        "scaleLog",
+        length_range,
+    );
+    length_meta.preload = Some(true);
+    let ncount_meta = variable_field_meta(
+        "ncount",
+        "N count",
+        Datatype::Integer,
+        "scaleLinear",
+        ncount_range,
+    );
+
+    let id = match &options.id {
+        Some(id) => id.clone(),
+        None => options
+            .blobdir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "blobdir".to_string()),
+    };
+    let meta = Meta {
+        id: id.clone(),
+        name: id,
+        record_type: if options.level == "contig" {
+            "contig".to_string()
+        } else {
+            "scaffold".to_string()
+        },
+        records: identifiers.len(),
+        revision: 0,
+        version: 1,
+        assembly: AssemblyMeta {
+            accession: "draft".to_string(),
+            level: options.level.clone(),
+            prefix: None,
+            alias: None,
+            bioproject: None,
+            biosample: None,
+            file: Some(options.fasta.clone()),
+            scaffold_count: Some(identifiers.len()),
+            span: Some(length_values.iter().sum()),
+            url: None,
+        },
+        fields: vec![identifiers_meta, gc_meta, length_meta, ncount_meta],
+        plot: PlotMeta {
+            x: Some("gc".to_string()),
+            z: Some("length".to_string()),
+            ..Default::default()
+        },
+        taxon: TaxonMeta {
+            name: "unnamed".to_string(),
+            class: None,
+            family: None,
+            genus: None,
+            kingdom: None,
+            order: None,
+            phylum: None,
+            superkingdom: None,
+            taxid: "0".to_string(),
+        },
+        field_list: None,
+        busco_list: None,
+        other: HashMap::from([("links".to_string(), serde_json::json!({}))]),
+    };
+
+    blobdir::write_field(&options.blobdir, "identifiers", &values_field(identifiers))?;
+    blobdir::write_field(&options.blobdir, "gc", &values_field(gc_values))?;
+    blobdir::write_field(&options.blobdir, "length", &values_field(length_values))?;
+    blobdir::write_field(&options.blobdir, "ncount", &values_field(ncount_values))?;
+    blobdir::write_meta(&options.blobdir, &meta)?;
+    Ok(())
+}
@@ -7,6 +7,7 @@ use anyhow;
 use crate::bam;
 use crate::cli;
 use crate::io;
+use crate::paf;
 
 pub use bam::BinnedCov;
 pub use cli::DepthOptions;
@@ -14,7 +15,27 @@ pub use cli::DepthOptions;
 /// Execute the `depth` subcommand from `blobtk`. Generate a BED file.
 pub fn depth(options: &cli::DepthOptions) -> Result<(), anyhow::Error> {
     let seq_names = io::get_list(&options.list_file);
-    let bam = bam::open_bam(&options.bam, &options.cram, &options.fasta, true);
-    bam::get_bed_file(bam, &seq_names, options, &None as &Option<Box<dyn Fn()>>);
+    if !options.paf.is_empty() {
+        let libraries = paf::open_libraries(options, &seq_names)?;
+        paf::get_bed_file(libraries, options, &None as &Option<Box<dyn Fn()>>)?;
+        return Ok(());
+    }
+    if options.stream {
+        let libraries = bam::open_stream_libraries(options)?;
+        bam::get_stream_bed_file(
+            libraries,
+            &seq_names,
+            options,
+            &None as &Option<Box<dyn Fn()>>,
+        )?;
+        return Ok(());
+    }
+    let libraries = bam::open_libraries(options)?;
+    bam::get_bed_file(
+        libraries,
+        &seq_names,
+        options,
+        &None as &Option<Box<dyn Fn()>>,
+    )?;
     Ok(())
 }
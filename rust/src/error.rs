@@ -13,6 +13,18 @@ pub enum Error {
     InvalidImageSuffix(String),
     #[error("Unable to process JSON: {0}")]
     SerdeError(String),
+    #[error("Alignment headers do not match: {0}")]
+    MismatchedHeaders(String),
+    #[error("Invalid option: {0}")]
+    InvalidOption(String),
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+    #[error("Unable to parse file: {0}")]
+    ParseError(String),
+    #[error("Unable to process alignments: {0}")]
+    HtslibError(String),
+    #[error("Unable to write file: {0}")]
+    WriteError(String),
 }
 
 impl From<std::io::Error> for Error {
@@ -21,8 +33,20 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<rust_htslib::errors::Error> for Error {
+    fn from(err: rust_htslib::errors::Error) -> Self {
+        Error::HtslibError(err.to_string())
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Self {
         Error::SerdeError(err.to_string())
     }
 }
+
+impl From<Error> for pyo3::PyErr {
+    fn from(err: Error) -> Self {
+        pyo3::exceptions::PyValueError::new_err(err.to_string())
+    }
+}
@@ -1,13 +1,19 @@
-use std::collections::HashSet;
-use std::io::Write;
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::ffi::c_void;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 extern crate needletail;
+use flate2::read::MultiGzDecoder;
 use needletail::parser::{write_fasta, LineEnding};
 use needletail::FastxReader;
+use rust_htslib::{faidx, htslib};
 
-use crate::fastq::{open_fastx, suffix_file_name};
-use crate::io::get_writer;
+use crate::error;
+use crate::fastq::{bin_suffixes, open_fastx, suffix_file_name};
+use crate::io::{get_writer, FileReport};
 use crate::utils::styled_progress_bar;
 
 fn trim_seq_id(input: &[u8]) -> Vec<u8> {
@@ -19,23 +25,156 @@ fn trim_seq_id(input: &[u8]) -> Vec<u8> {
         .collect()
 }
 
-fn subsample_fasta<F: Fn()>(
-    seq_names: &HashSet<Vec<u8>>,
+/// Reverse complement a nucleotide sequence, keeping case and IUPAC
+/// ambiguity codes.
+pub(crate) fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|base| match base {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' | b'U' => b'A',
+            b'a' => b't',
+            b'c' => b'g',
+            b'g' => b'c',
+            b't' | b'u' => b'a',
+            b'R' => b'Y',
+            b'Y' => b'R',
+            b'K' => b'M',
+            b'M' => b'K',
+            b'B' => b'V',
+            b'V' => b'B',
+            b'D' => b'H',
+            b'H' => b'D',
+            b'r' => b'y',
+            b'y' => b'r',
+            b'k' => b'm',
+            b'm' => b'k',
+            b'b' => b'v',
+            b'v' => b'b',
+            b'd' => b'h',
+            b'h' => b'd',
+            other => *other,
+        })
+        .collect()
+}
+
+/// A sequence region in samtools `name:start-end` notation, using 1-based
+/// inclusive coordinates. `name:start` runs to the end of the sequence and
+/// a bare `name` is the whole sequence.
+///
+/// # Examples
+///
+/// ```
+/// # use crate::blobtk::fasta::Region;
+/// let region: Region = "contig_1:101-200".parse().unwrap();
+/// assert_eq!(region.seq_name, "contig_1");
+/// assert_eq!((region.start, region.end), (101, Some(200)));
+/// let region: Region = "contig_1:1,001".parse().unwrap();
+/// assert_eq!((region.start, region.end), (1001, None));
+/// let region: Region = "contig_2".parse().unwrap();
+/// assert_eq!((region.start, region.end), (1, None));
+/// assert!("contig_1:200-100".parse::<Region>().is_err());
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub seq_name: String,
+    pub start: usize,
+    pub end: Option<usize>,
+}
+
+impl FromStr for Region {
+    type Err = error::Error;
+
+    fn from_str(region: &str) -> Result<Self, Self::Err> {
+        let invalid = || error::Error::InvalidOption(format!("region {}", region));
+        let parse_position = |value: &str| value.replace(',', "").parse::<usize>();
+        let (seq_name, range) = match region.rsplit_once(':') {
+            Some((name, range))
+                if !name.is_empty()
+                    && !range.is_empty()
+                    && range
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || c == ',' || c == '-') =>
+            {
+                (name, Some(range))
+            }
+            _ => (region, None),
+        };
+        let (start, end) = match range {
+            None => (1, None),
+            Some(range) => match range.split_once('-') {
+                Some((start, "")) => (parse_position(start).map_err(|_| invalid())?, None),
+                Some((start, end)) => (
+                    parse_position(start).map_err(|_| invalid())?,
+                    Some(parse_position(end).map_err(|_| invalid())?),
+                ),
+                None => (parse_position(range).map_err(|_| invalid())?, None),
+            },
+        };
+        if seq_name.is_empty() || start == 0 || end.map_or(false, |end| end < start) {
+            return Err(invalid());
+        }
+        Ok(Region {
+            seq_name: seq_name.to_string(),
+            start,
+            end,
+        })
+    }
+}
+
+/// Write a FASTA record, reverse complemented with a `/rc` name suffix
+/// (as `samtools faidx -i`) when `revcomp` is set.
+fn write_record(
+    id: &[u8],
+    seq: &[u8],
+    revcomp: bool,
+    writer: &mut Box<dyn Write>,
+    report: &mut FileReport,
+) -> Result<(), error::Error> {
+    let result = if revcomp {
+        let id = [trim_seq_id(id).as_slice(), b"/rc"].concat();
+        write_fasta(&id, &reverse_complement(seq), writer, LineEnding::Unix)
+    } else {
+        write_fasta(id, seq, writer, LineEnding::Unix)
+    };
+    result.map_err(|err| error::Error::WriteError(format!("unable to write FASTA: {}", err)))?;
+    report.add(seq.len());
+    Ok(())
+}
+
+/// Write each sequence to the output chosen by `select`, if any. When a
+/// `limit` is set, stop once that many sequences have been written.
+fn subsample_fasta<S, F>(
+    select: S,
+    limit: Option<usize>,
     mut reader: Box<dyn FastxReader>,
-    writer: &mut dyn Write,
+    writers: &mut [Box<dyn Write>],
+    reports: &mut [FileReport],
+    revcomp: bool,
     callback: &Option<F>,
-) {
-    let total = seq_names.len();
+) -> Result<(), error::Error>
+where
+    S: Fn(&Vec<u8>) -> Option<usize>,
+    F: Fn(),
+{
+    let total = limit.unwrap_or(0);
     let progress_bar = styled_progress_bar(total, "Subsampling FASTA");
 
     while let Some(record) = reader.next() {
         let seqrec = record.as_ref().expect("invalid record");
         let seq_id: Vec<u8> = trim_seq_id(seqrec.id());
-        if seq_names.contains(&seq_id) {
-            write_fasta(seqrec.id(), &seqrec.seq(), writer, LineEnding::Unix)
-                .expect("Unable to write FASTA");
+        if let Some(index) = select(&seq_id) {
+            write_record(
+                seqrec.id(),
+                &seqrec.seq(),
+                revcomp,
+                &mut writers[index],
+                &mut reports[index],
+            )?;
             progress_bar.inc(1);
-            if progress_bar.position() as usize == total {
+            if limit == Some(progress_bar.position() as usize) {
                 break;
             }
         }
@@ -46,27 +185,335 @@ fn subsample_fasta<F: Fn()>(
         }
     }
     progress_bar.finish();
+    Ok(())
+}
+
+/// Check whether a FASTA file can be read through a `.fai` index, i.e. it
+/// is uncompressed or BGZF compressed rather than plain gzip.
+fn is_indexable(fasta_path: &Path) -> bool {
+    if fasta_path == Path::new("-") {
+        return false;
+    }
+    let mut magic = [0u8; 14];
+    let read = match File::open(fasta_path).and_then(|mut file| file.read(&mut magic)) {
+        Ok(read) => read,
+        Err(_) => return false,
+    };
+    if read < 2 || magic[..2] != [0x1f, 0x8b] {
+        return true;
+    }
+    // BGZF blocks are gzip members with a `BC` extra subfield
+    read == 14 && magic[3] & 4 != 0 && magic[12..14] == *b"BC"
+}
+
+/// A `.fai` index line giving the length of a sequence and the layout of
+/// its lines in the FASTA file.
+struct FaiRecord {
+    seq_name: String,
+    length: usize,
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+}
+
+impl FaiRecord {
+    /// Offset of the first byte after the last sequence line.
+    fn end_offset(&self) -> u64 {
+        if self.line_bases == 0 {
+            return self.offset;
+        }
+        let length = self.length as u64;
+        let partial = length % self.line_bases;
+        let mut end = self.offset + length / self.line_bases * self.line_width;
+        if partial > 0 {
+            end += partial + self.line_width - self.line_bases;
+        }
+        end
+    }
+}
+
+fn index_path(fasta_path: &Path, suffix: &str) -> PathBuf {
+    let mut path = fasta_path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+fn read_fai(fai_path: &Path) -> Result<Vec<FaiRecord>, error::Error> {
+    let mut records = vec![];
+    for line in BufReader::new(File::open(fai_path)?).lines() {
+        let line = line?;
+        let invalid = || error::Error::ParseError(format!("{}: {}", fai_path.display(), line));
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 5 {
+            return Err(invalid());
+        }
+        records.push(FaiRecord {
+            seq_name: fields[0].to_string(),
+            length: fields[1].parse().map_err(|_| invalid())?,
+            offset: fields[2].parse().map_err(|_| invalid())?,
+            line_bases: fields[3].parse().map_err(|_| invalid())?,
+            line_width: fields[4].parse().map_err(|_| invalid())?,
+        });
+    }
+    Ok(records)
+}
+
+/// Read the (compressed, uncompressed) offset pairs of the BGZF blocks
+/// listed in a `.gzi` index.
+fn read_gzi(gzi_path: &Path) -> Result<Vec<(u64, u64)>, error::Error> {
+    let mut reader = BufReader::new(File::open(gzi_path)?);
+    let mut read_u64 = || -> Result<u64, std::io::Error> {
+        let mut bytes = [0u8; 8];
+        reader.read_exact(&mut bytes)?;
+        Ok(u64::from_le_bytes(bytes))
+    };
+    let count = read_u64()?;
+    let mut blocks = vec![];
+    for _ in 0..count {
+        blocks.push((read_u64()?, read_u64()?));
+    }
+    Ok(blocks)
+}
+
+/// A FASTA file opened through a samtools-compatible `.fai` index (and
+/// `.gzi` for BGZF input), which htslib builds alongside the file if missing.
+struct IndexedFasta {
+    fasta_path: PathBuf,
+    reader: faidx::Reader,
+    records: Vec<FaiRecord>,
+    gzi: Option<Vec<(u64, u64)>>,
+}
+
+impl IndexedFasta {
+    fn open(fasta_path: &Path) -> Option<Self> {
+        let reader = faidx::Reader::from_path(fasta_path).ok()?;
+        let records = read_fai(&index_path(fasta_path, ".fai")).ok()?;
+        let gzi = match rust_htslib::bgzf::is_bgzip(fasta_path) {
+            Ok(true) => Some(read_gzi(&index_path(fasta_path, ".gzi")).ok()?),
+            Ok(false) => None,
+            Err(_) => return None,
+        };
+        Some(IndexedFasta {
+            fasta_path: fasta_path.to_path_buf(),
+            reader,
+            records,
+            gzi,
+        })
+    }
+
+    /// Fetch bases `begin` to `end` (0-based, inclusive) of a sequence that
+    /// is listed in the index. htslib allocates a new buffer for every
+    /// fetch, so it is copied and freed here.
+    fn fetch_seq(&self, seq_name: &str, begin: usize, end: usize) -> Result<Vec<u8>, error::Error> {
+        let bytes = self.reader.fetch_seq(seq_name, begin, end)?;
+        let seq = bytes.to_vec();
+        unsafe { htslib::free(bytes.as_ptr() as *mut c_void) };
+        Ok(seq)
+    }
+
+    /// Read the uncompressed bytes from `start` up to `end`, starting from
+    /// the nearest BGZF block listed in the `.gzi` index for BGZF input.
+    fn read_bytes(&self, start: u64, end: u64) -> Result<Vec<u8>, error::Error> {
+        let mut file = File::open(&self.fasta_path)?;
+        let reader: Box<dyn Read> = match &self.gzi {
+            None => {
+                file.seek(SeekFrom::Start(start))?;
+                Box::new(file)
+            }
+            Some(blocks) => {
+                let (compressed, uncompressed) = blocks
+                    .iter()
+                    .rev()
+                    .find(|(_, uncompressed)| *uncompressed <= start)
+                    .copied()
+                    .unwrap_or((0, 0));
+                file.seek(SeekFrom::Start(compressed))?;
+                let mut decoder = MultiGzDecoder::new(file);
+                std::io::copy(
+                    &mut (&mut decoder).take(start - uncompressed),
+                    &mut std::io::sink(),
+                )?;
+                Box::new(decoder)
+            }
+        };
+        let mut bytes = vec![];
+        reader.take(end - start).read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Read the full header line of a record, which is the last line
+    /// starting with `>` between the end of the previous sequence and
+    /// the start of this one.
+    fn read_header(&self, start: u64, record: &FaiRecord) -> Result<Vec<u8>, error::Error> {
+        let bytes = self.read_bytes(start, record.offset)?;
+        let header = bytes
+            .split(|&byte| byte == b'\n')
+            .rfind(|line| line.starts_with(b">"))
+            .map(|line| line[1..].strip_suffix(b"\r").unwrap_or(&line[1..]).to_vec());
+        Ok(header.unwrap_or_else(|| record.seq_name.as_bytes().to_vec()))
+    }
+}
+
+/// Write the listed sequences and regions by seeking to each record through
+/// the FASTA index. Whole sequences are written in file order with their
+/// full header line, followed by regions in the order given.
+fn subsample_indexed<F: Fn()>(
+    seq_names: &HashSet<Vec<u8>>,
+    regions: &[Region],
+    fasta: &IndexedFasta,
+    writer: &mut Box<dyn Write>,
+    report: &mut FileReport,
+    revcomp: bool,
+    callback: &Option<F>,
+) -> Result<(), error::Error> {
+    let progress_bar = styled_progress_bar(seq_names.len() + regions.len(), "Subsampling FASTA");
+    let mut previous_end = 0;
+    for record in &fasta.records {
+        if seq_names.contains(record.seq_name.as_bytes()) {
+            let header = fasta.read_header(previous_end, record)?;
+            let seq = match record.length {
+                0 => vec![],
+                length => fasta.fetch_seq(&record.seq_name, 0, length - 1)?,
+            };
+            write_record(&header, &seq, revcomp, writer, report)?;
+            progress_bar.inc(1);
+            match callback {
+                Some(cb) => cb(),
+                None => (),
+            }
+        }
+        previous_end = record.end_offset();
+    }
+    let seq_lengths: HashMap<&str, usize> = fasta
+        .records
+        .iter()
+        .map(|record| (record.seq_name.as_str(), record.length))
+        .collect();
+    for region in regions {
+        let seq_length = match seq_lengths.get(region.seq_name.as_str()) {
+            Some(seq_length) => *seq_length,
+            None => {
+                return Err(error::Error::InvalidOption(format!(
+                    "region sequence {} not found in FASTA index",
+                    region.seq_name
+                )))
+            }
+        };
+        let end = region.end.unwrap_or(seq_length).min(seq_length);
+        if region.start > end {
+            return Err(error::Error::InvalidOption(format!(
+                "region {}:{} is beyond the end of the sequence",
+                region.seq_name, region.start
+            )));
+        }
+        let seq = fasta.fetch_seq(&region.seq_name, region.start - 1, end - 1)?;
+        let id = format!("{}:{}-{}", region.seq_name, region.start, end);
+        write_record(id.as_bytes(), &seq, revcomp, writer, report)?;
+        progress_bar.inc(1);
+        match callback {
+            Some(cb) => cb(),
+            None => (),
+        }
+    }
+    progress_bar.finish();
+    Ok(())
 }
 
+/// Write the listed sequences and regions to a new FASTA file. Uncompressed
+/// and BGZF input is read through a `.fai` index, otherwise the file is
+/// scanned until every listed sequence has been written.
+#[allow(clippy::too_many_arguments)]
 pub fn subsample<F: Fn()>(
     seq_names: &HashSet<Vec<u8>>,
+    regions: &[String],
     fasta_path: &Option<PathBuf>,
     fasta_out: &bool,
+    revcomp: &bool,
     suffix: &String,
     callback: &Option<F>,
-) {
-    if fasta_path.is_none() {
-        return;
+) -> Result<Vec<FileReport>, error::Error> {
+    let fasta_path = match fasta_path {
+        Some(path) if *fasta_out => path,
+        _ => return Ok(vec![]),
+    };
+    if seq_names.is_empty() && regions.is_empty() {
+        return Ok(vec![]);
     }
-    if !fasta_out {
-        return;
+    let regions = regions
+        .iter()
+        .map(|region| region.parse())
+        .collect::<Result<Vec<Region>, error::Error>>()?;
+    let index = if is_indexable(fasta_path) {
+        IndexedFasta::open(fasta_path)
+    } else {
+        None
+    };
+    if index.is_none() && !regions.is_empty() {
+        return Err(error::Error::InvalidOption(format!(
+            "--region needs an uncompressed or bgzipped FASTA file that can be indexed, not {}",
+            fasta_path.display()
+        )));
     }
 
+    let out_path = suffix_file_name(fasta_path, suffix);
+    let mut reports = vec![FileReport::new(&out_path)];
+    let mut writers = vec![get_writer(&Some(out_path))];
+
+    match index {
+        Some(fasta) => subsample_indexed(
+            seq_names,
+            &regions,
+            &fasta,
+            &mut writers[0],
+            &mut reports[0],
+            *revcomp,
+            callback,
+        )?,
+        None => {
+            let select = |seq_id: &Vec<u8>| seq_names.contains(seq_id).then_some(0);
+            if let Some(r) = open_fastx(&Some(fasta_path.clone())) {
+                subsample_fasta(
+                    select,
+                    Some(seq_names.len()),
+                    r,
+                    &mut writers,
+                    &mut reports,
+                    *revcomp,
+                    callback,
+                )?;
+            }
+        }
+    }
+    Ok(reports)
+}
+
+/// Write sequences to one FASTA file per bin, with sequences missing from
+/// `seq_bins` written to an `unassigned` file.
+pub fn partition<F: Fn()>(
+    seq_bins: &HashMap<Vec<u8>, usize>,
+    bins: &[String],
+    fasta_path: &Option<PathBuf>,
+    suffix: &String,
+    callback: &Option<F>,
+) -> Result<Vec<FileReport>, error::Error> {
+    if fasta_path.is_none() {
+        return Ok(vec![]);
+    }
     let reader = open_fastx(fasta_path);
-    let out_path = suffix_file_name(fasta_path.as_ref().unwrap(), suffix);
-    let mut writer = get_writer(&Some(out_path));
+    let out_paths: Vec<PathBuf> = bin_suffixes(bins, suffix)?
+        .iter()
+        .map(|bin_suffix| suffix_file_name(fasta_path.as_ref().unwrap(), bin_suffix))
+        .collect();
+    let mut reports: Vec<FileReport> = out_paths.iter().map(|path| FileReport::new(path)).collect();
+    let mut writers: Vec<Box<dyn Write>> = out_paths
+        .into_iter()
+        .map(|path| get_writer(&Some(path)))
+        .collect();
+    let unassigned = bins.len();
+    let select = |seq_id: &Vec<u8>| Some(*seq_bins.get(seq_id).unwrap_or(&unassigned));
 
     if let Some(r) = reader {
-        subsample_fasta(seq_names, r, &mut *writer, callback);
+        subsample_fasta(select, None, r, &mut writers, &mut reports, false, callback)?;
     }
+    Ok(reports)
 }
@@ -1,12 +1,16 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, VecDeque};
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
 extern crate needletail;
-use needletail::parser::{write_fastq, LineEnding};
+use needletail::errors::ParseErrorKind;
+use needletail::parser::{write_fastq, LineEnding, SequenceRecord};
 use needletail::{parse_fastx_file, FastxReader};
 
-use crate::io::get_writer;
+use crate::cli::FilterOptions;
+use crate::error;
+use crate::io::{get_writer, FileReport};
+use crate::readset::ReadSet;
 use crate::utils::styled_progress_bar;
 
 pub fn open_fastx(fastx_path: &Option<PathBuf>) -> Option<Box<dyn FastxReader>> {
@@ -16,61 +20,292 @@ pub fn open_fastx(fastx_path: &Option<PathBuf>) -> Option<Box<dyn FastxReader>>
     reader
 }
 
-fn trim_read_id(input: &[u8]) -> Vec<u8> {
-    input
+fn open_fastq(fastq_path: &PathBuf) -> Result<Box<dyn FastxReader>, error::Error> {
+    parse_fastx_file(fastq_path).map_err(|err| {
+        let message = format!("{}: {}", fastq_path.display(), err);
+        match err.kind {
+            ParseErrorKind::Io => error::Error::FileNotFound(message),
+            _ => error::Error::ParseError(message),
+        }
+    })
+}
+
+/// Trim a read ID to the name shared by both mates, removing any
+/// comment (including Casava 1.8 `1:N:0:ATCACG` style read numbers)
+/// and a trailing `/1` or `/2`.
+pub(crate) fn trim_read_id(input: &[u8]) -> Vec<u8> {
+    let mut id: Vec<u8> = input
         .iter()
         .copied()
-        .by_ref()
-        .take_while(|&x| x != b' ' && x != b'/')
-        .collect()
+        .take_while(|x| !x.is_ascii_whitespace())
+        .collect();
+    if id.ends_with(b"/1") || id.ends_with(b"/2") {
+        id.truncate(id.len() - 2);
+    }
+    id
 }
 
-fn subsample_paired<F: Fn()>(
-    read_names: &HashSet<Vec<u8>>,
-    mut reader: Box<dyn FastxReader>,
-    mut paired_reader: Box<dyn FastxReader>,
-    writer: &mut dyn Write,
-    paired_writer: &mut dyn Write,
-    read_suffix: &[Vec<u8>; 2],
+/// How paired reads are laid out in the input and output FASTQ files.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PairedLayout {
+    /// Mates are interleaved in a single input file
+    pub interleaved: bool,
+    /// Write mates interleaved in a single output file
+    pub interleaved_out: bool,
+    /// Buffer mates that are out of order until they can be paired
+    pub repair: bool,
+}
+
+impl PairedLayout {
+    pub fn from_options(options: &FilterOptions) -> PairedLayout {
+        PairedLayout {
+            interleaved: options.interleaved,
+            interleaved_out: options.interleaved_out,
+            repair: options.repair,
+        }
+    }
+}
+
+/// An owned copy of a FASTQ record, so mates can be buffered.
+#[derive(Debug)]
+struct FastqRecord {
+    id: Vec<u8>,
+    seq: Vec<u8>,
+    qual: Option<Vec<u8>>,
+}
+
+impl FastqRecord {
+    fn new(seqrec: &SequenceRecord) -> FastqRecord {
+        FastqRecord {
+            id: seqrec.id().to_vec(),
+            seq: seqrec.seq().to_vec(),
+            qual: seqrec.qual().map(|qual| qual.to_vec()),
+        }
+    }
+
+    fn read_id(&self) -> Vec<u8> {
+        trim_read_id(&self.id)
+    }
+
+    /// Mate number from a trailing `/1` or `/2` on the read name or a
+    /// Casava 1.8 `1:N:0:ATCACG` style comment.
+    fn mate_number(&self) -> Option<u8> {
+        let mut fields = self.id.split(|x| x.is_ascii_whitespace());
+        let name = fields.next().unwrap_or_default();
+        if name.ends_with(b"/1") {
+            return Some(1);
+        }
+        if name.ends_with(b"/2") {
+            return Some(2);
+        }
+        match fields.find(|field| !field.is_empty()) {
+            Some([number @ (b'1' | b'2'), b':', ..]) => Some(number - b'0'),
+            _ => None,
+        }
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> Result<(), error::Error> {
+        write_fastq(
+            &self.id,
+            &self.seq,
+            self.qual.as_deref(),
+            writer,
+            LineEnding::Unix,
+        )
+        .map_err(|err| error::Error::WriteError(format!("unable to write FASTQ: {}", err)))
+    }
+}
+
+fn next_record(reader: &mut Box<dyn FastxReader>) -> Result<Option<FastqRecord>, error::Error> {
+    match reader.next() {
+        None => Ok(None),
+        Some(Ok(seqrec)) => Ok(Some(FastqRecord::new(&seqrec))),
+        Some(Err(err)) => Err(error::Error::ParseError(err.to_string())),
+    }
+}
+
+fn unpaired_error(id_1: &[u8], id_2: &[u8]) -> error::Error {
+    error::Error::ParseError(format!(
+        "FASTQ mates are not in the same order ({} and {}), use --repair to re-pair them",
+        String::from_utf8_lossy(id_1),
+        String::from_utf8_lossy(id_2)
+    ))
+}
+
+/// Reads mate pairs from two FASTQ files or from one interleaved file.
+/// With `repair` set, mates that are out of order are buffered by read
+/// ID until the other mate is found.
+struct PairedReader {
+    reader: Box<dyn FastxReader>,
+    paired_reader: Option<Box<dyn FastxReader>>,
+    repair: bool,
+    buffers: [HashMap<Vec<u8>, FastqRecord>; 2],
+    pending: VecDeque<(FastqRecord, FastqRecord)>,
+}
+
+impl PairedReader {
+    fn new(
+        reader: Box<dyn FastxReader>,
+        paired_reader: Option<Box<dyn FastxReader>>,
+        repair: bool,
+    ) -> PairedReader {
+        PairedReader {
+            reader,
+            paired_reader,
+            repair,
+            buffers: [HashMap::new(), HashMap::new()],
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Number of buffered reads still waiting for a mate.
+    fn unpaired(&self) -> usize {
+        self.buffers[0].len() + self.buffers[1].len()
+    }
+
+    fn next_pair(&mut self) -> Result<Option<(FastqRecord, FastqRecord)>, error::Error> {
+        if let Some(pair) = self.pending.pop_front() {
+            return Ok(Some(pair));
+        }
+        if self.paired_reader.is_none() {
+            return self.next_interleaved();
+        }
+        self.next_separate()
+    }
+
+    fn next_interleaved(&mut self) -> Result<Option<(FastqRecord, FastqRecord)>, error::Error> {
+        if self.repair {
+            while let Some(record) = next_record(&mut self.reader)? {
+                let read_id = record.read_id();
+                match self.buffers[0].remove(&read_id) {
+                    Some(mate) => {
+                        // the second mate seen is read 1 when read 2 came first
+                        return match (mate.mate_number(), record.mate_number()) {
+                            (Some(2), _) | (_, Some(1)) => Ok(Some((record, mate))),
+                            _ => Ok(Some((mate, record))),
+                        };
+                    }
+                    None => {
+                        self.buffers[0].insert(read_id, record);
+                    }
+                }
+            }
+            return Ok(None);
+        }
+        let record = match next_record(&mut self.reader)? {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+        let mate = match next_record(&mut self.reader)? {
+            Some(mate) => mate,
+            None => {
+                return Err(error::Error::ParseError(
+                    "interleaved FASTQ has an odd number of records".to_string(),
+                ))
+            }
+        };
+        if record.read_id() != mate.read_id() {
+            return Err(unpaired_error(&record.id, &mate.id));
+        }
+        Ok(Some((record, mate)))
+    }
+
+    fn next_separate(&mut self) -> Result<Option<(FastqRecord, FastqRecord)>, error::Error> {
+        let paired_reader = self.paired_reader.as_mut().unwrap();
+        loop {
+            let record = next_record(&mut self.reader)?;
+            let mate = next_record(paired_reader)?;
+            match (record, mate) {
+                (None, None) => return Ok(None),
+                (Some(record), Some(mate)) if record.read_id() == mate.read_id() => {
+                    return Ok(Some((record, mate)))
+                }
+                (record, mate) if self.repair => {
+                    if let Some(record) = record {
+                        let read_id = record.read_id();
+                        match self.buffers[1].remove(&read_id) {
+                            Some(mate) => self.pending.push_back((record, mate)),
+                            None => {
+                                self.buffers[0].insert(read_id, record);
+                            }
+                        }
+                    }
+                    if let Some(mate) = mate {
+                        let read_id = mate.read_id();
+                        match self.buffers[0].remove(&read_id) {
+                            Some(record) => self.pending.push_back((record, mate)),
+                            None => {
+                                self.buffers[1].insert(read_id, mate);
+                            }
+                        }
+                    }
+                    if let Some(pair) = self.pending.pop_front() {
+                        return Ok(Some(pair));
+                    }
+                }
+                (Some(record), Some(mate)) => return Err(unpaired_error(&record.id, &mate.id)),
+                _ => {
+                    return Err(error::Error::ParseError(
+                        "paired FASTQ files have different numbers of records".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+/// An output FASTQ file and counts of the records written to it.
+struct Output {
+    writer: Box<dyn Write>,
+    report: FileReport,
+}
+
+impl Output {
+    fn new(path: PathBuf) -> Output {
+        Output {
+            report: FileReport::new(&path),
+            writer: get_writer(&Some(path)),
+        }
+    }
+
+    fn write(&mut self, record: &FastqRecord) -> Result<(), error::Error> {
+        record.write(&mut self.writer)?;
+        self.report.add(record.seq.len());
+        Ok(())
+    }
+}
+
+/// Write each read pair to the output chosen by `select`, if any. When a
+/// `limit` is set, stop once that many pairs have been written. Second
+/// mates are written to `outputs` too when there are no `paired_outputs`.
+fn subsample_paired<S, F>(
+    select: S,
+    limit: Option<usize>,
+    mut reader: PairedReader,
+    outputs: &mut [Output],
+    paired_outputs: &mut Option<Vec<Output>>,
     callback: &Option<F>,
-) {
-    let total = read_names.len();
+) -> Result<(), error::Error>
+where
+    S: Fn(&Vec<u8>) -> Option<usize>,
+    F: Fn(),
+{
+    let total = limit.unwrap_or(0);
     let progress_bar = styled_progress_bar(total, "Subsampling FASTQ");
 
-    while let Some(record) = reader.next() {
-        let seqrec = record.expect("invalid record");
-        let paired_record = paired_reader.next().unwrap();
-        let paired_seqrec = paired_record.expect("invalid paired record");
-        let mut seq_id: Vec<u8> = trim_read_id(seqrec.id());
-        let mut paired_id: Vec<u8> = trim_read_id(paired_seqrec.id());
-        if seq_id != paired_id {
-            panic!(
-                "Fasta files not sorted consistently {:?} ne {:?}",
-                String::from_utf8(seq_id),
-                String::from_utf8(paired_id)
-            );
-        }
-        if read_names.contains(&seq_id) || read_names.contains(&paired_id) {
-            seq_id.extend(&read_suffix[0]);
-            write_fastq(
-                seqrec.id(),
-                &seqrec.seq(),
-                seqrec.qual(),
-                writer,
-                LineEnding::Unix,
-            )
-            .expect("Unable to write FASTQ");
-            paired_id.extend(&read_suffix[1]);
-            write_fastq(
-                paired_seqrec.id(),
-                &paired_seqrec.seq(),
-                paired_seqrec.qual(),
-                paired_writer,
-                LineEnding::Unix,
-            )
-            .expect("Unable to write FASTQ");
+    while let Some((record, mate)) = reader.next_pair()? {
+        if let Some(index) = select(&record.read_id()) {
+            outputs[index].write(&record)?;
+            outputs[index].report.pairs += 1;
+            match paired_outputs.as_mut() {
+                Some(paired_outputs) => {
+                    paired_outputs[index].write(&mate)?;
+                    paired_outputs[index].report.pairs += 1;
+                }
+                None => outputs[index].write(&mate)?,
+            }
             progress_bar.inc(1);
-            if progress_bar.position() as usize == total {
+            if limit == Some(progress_bar.position() as usize) {
                 break;
             }
         }
@@ -80,33 +315,36 @@ fn subsample_paired<F: Fn()>(
         }
     }
     progress_bar.finish();
+    if reader.unpaired() > 0 {
+        eprintln!(
+            "Skipped {} reads with no mate in the paired FASTQ input",
+            reader.unpaired()
+        );
+    }
+    Ok(())
 }
 
-fn subsample_single<F: Fn()>(
-    read_names: &HashSet<Vec<u8>>,
+/// Write each read to the output chosen by `select`, if any. When a
+/// `limit` is set, stop once that many reads have been written.
+fn subsample_single<S, F>(
+    select: S,
+    limit: Option<usize>,
     mut reader: Box<dyn FastxReader>,
-    writer: &mut dyn Write,
-    read_suffix: &[Vec<u8>; 2],
+    outputs: &mut [Output],
     callback: &Option<F>,
-) {
-    let total = read_names.len();
+) -> Result<(), error::Error>
+where
+    S: Fn(&Vec<u8>) -> Option<usize>,
+    F: Fn(),
+{
+    let total = limit.unwrap_or(0);
     let progress_bar = styled_progress_bar(total, "Subsampling FASTQ");
 
-    while let Some(record) = reader.next() {
-        let seqrec = record.as_ref().expect("invalid record");
-        let mut seq_id: Vec<u8> = trim_read_id(seqrec.id());
-        if read_names.contains(&seq_id) {
-            seq_id.extend(&read_suffix[0]);
-            write_fastq(
-                seqrec.id(),
-                &seqrec.seq(),
-                seqrec.qual(),
-                writer,
-                LineEnding::Unix,
-            )
-            .expect("Unable to write FASTQ");
+    while let Some(record) = next_record(&mut reader)? {
+        if let Some(index) = select(&record.read_id()) {
+            outputs[index].write(&record)?;
             progress_bar.inc(1);
-            if progress_bar.position() as usize == total {
+            if limit == Some(progress_bar.position() as usize) {
                 break;
             }
         }
@@ -116,6 +354,7 @@ fn subsample_single<F: Fn()>(
         }
     }
     progress_bar.finish();
+    Ok(())
 }
 
 pub fn suffix_file_name(path: impl AsRef<Path>, suffix: &String) -> PathBuf {
@@ -147,46 +386,151 @@ pub fn suffix_file_name(path: impl AsRef<Path>, suffix: &String) -> PathBuf {
     result
 }
 
-fn set_read_suffix(read_names: &HashSet<Vec<u8>>) -> [Vec<u8>; 2] {
-    let first_name = read_names.iter().next().unwrap();
-    if first_name.contains(&b'/') {
-        return [vec![] as Vec<u8>, vec![] as Vec<u8>];
-    }
-    [vec![b'/', b'1'], vec![b'/', b'2']]
-}
-
 pub fn subsample<F: Fn()>(
-    read_names: &HashSet<Vec<u8>>,
+    read_names: &ReadSet,
     fastq_path_1: &Option<PathBuf>,
     fastq_path_2: &Option<PathBuf>,
     fastq_out: &bool,
     suffix: &String,
+    layout: &PairedLayout,
     callback: &Option<F>,
-) {
+) -> Result<Vec<FileReport>, error::Error> {
     if fastq_path_1.is_none() {
-        return;
+        return Ok(vec![]);
     }
     if !fastq_out {
-        return;
-    }
-    let reader = open_fastx(fastq_path_1);
-    let paired_reader = open_fastx(fastq_path_2);
-    let read_suffix = set_read_suffix(read_names);
-    let out_path = suffix_file_name(fastq_path_1.as_ref().unwrap(), suffix);
-    let mut writer = get_writer(&Some(out_path));
-    if let Some(pr) = paired_reader {
-        let paired_out_path = suffix_file_name(fastq_path_2.as_ref().unwrap(), suffix);
-        let mut paired_writer = get_writer(&Some(paired_out_path));
-        subsample_paired(
-            read_names,
-            reader.unwrap(),
-            pr,
-            &mut *writer,
-            &mut *paired_writer,
-            &read_suffix,
-            callback,
-        );
-    } else if let Some(r) = reader {
-        subsample_single(read_names, r, &mut *writer, &read_suffix, callback);
+        return Ok(vec![]);
+    }
+    let select = |read_name: &Vec<u8>| read_names.contains(read_name).then_some(0);
+    write_selected(
+        select,
+        Some(read_names.len()),
+        fastq_path_1,
+        fastq_path_2,
+        &[suffix.clone()],
+        layout,
+        callback,
+    )
+}
+
+fn open_outputs(fastq_path: &Path, suffixes: &[String]) -> Vec<Output> {
+    suffixes
+        .iter()
+        .map(|suffix| Output::new(suffix_file_name(fastq_path, suffix)))
+        .collect()
+}
+
+/// Open the input FASTQ file(s) and write selected reads to one output
+/// (or pair of outputs) per suffix.
+fn write_selected<S, F>(
+    select: S,
+    limit: Option<usize>,
+    fastq_path_1: &Option<PathBuf>,
+    fastq_path_2: &Option<PathBuf>,
+    suffixes: &[String],
+    layout: &PairedLayout,
+    callback: &Option<F>,
+) -> Result<Vec<FileReport>, error::Error>
+where
+    S: Fn(&Vec<u8>) -> Option<usize>,
+    F: Fn(),
+{
+    let fastq_path_1 = match fastq_path_1 {
+        Some(path) => path,
+        None => return Ok(vec![]),
+    };
+    let reader = open_fastq(fastq_path_1)?;
+    let mut outputs = open_outputs(fastq_path_1, suffixes);
+    let mut paired_outputs = None;
+    match fastq_path_2 {
+        Some(fastq_path_2) => {
+            let paired_reader =
+                PairedReader::new(reader, Some(open_fastq(fastq_path_2)?), layout.repair);
+            if !layout.interleaved_out {
+                paired_outputs = Some(open_outputs(fastq_path_2, suffixes));
+            }
+            subsample_paired(
+                select,
+                limit,
+                paired_reader,
+                &mut outputs,
+                &mut paired_outputs,
+                callback,
+            )?;
+        }
+        None if layout.interleaved => {
+            let paired_reader = PairedReader::new(reader, None, layout.repair);
+            subsample_paired(
+                select,
+                limit,
+                paired_reader,
+                &mut outputs,
+                &mut paired_outputs,
+                callback,
+            )?;
+        }
+        None => subsample_single(select, limit, reader, &mut outputs, callback)?,
+    }
+    Ok(outputs
+        .into_iter()
+        .chain(paired_outputs.into_iter().flatten())
+        .map(|output| output.report)
+        .collect())
+}
+
+/// Write reads to one FASTQ file (or pair of files) per bin in a single pass.
+/// `read_bins` maps read names to an index in `bins`, other reads are
+/// written to an `unassigned` file.
+pub fn partition<F: Fn()>(
+    read_bins: &HashMap<Vec<u8>, usize>,
+    bins: &[String],
+    fastq_path_1: &Option<PathBuf>,
+    fastq_path_2: &Option<PathBuf>,
+    suffix: &String,
+    layout: &PairedLayout,
+    callback: &Option<F>,
+) -> Result<Vec<FileReport>, error::Error> {
+    let unassigned = bins.len();
+    let select = |read_name: &Vec<u8>| Some(*read_bins.get(read_name).unwrap_or(&unassigned));
+    let suffixes = bin_suffixes(bins, suffix)?;
+    write_selected(
+        select,
+        None,
+        fastq_path_1,
+        fastq_path_2,
+        &suffixes,
+        layout,
+        callback,
+    )
+}
+
+/// Replace characters that are unsafe in file names with underscores.
+pub fn bin_file_name(bin: &str) -> String {
+    bin.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// File name suffix for each bin, followed by the `unassigned` bin. Bins
+/// that would share an output file are rejected rather than overwritten.
+pub fn bin_suffixes(bins: &[String], suffix: &str) -> Result<Vec<String>, error::Error> {
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut suffixes = vec![];
+    for bin in bins.iter().map(String::as_str).chain(["unassigned"]) {
+        let file_name = bin_file_name(bin);
+        if let Some(other) = seen.insert(file_name.clone(), bin) {
+            return Err(error::Error::InvalidOption(format!(
+                "bins {:?} and {:?} would both be written to {:?} files",
+                other, bin, file_name
+            )));
+        }
+        suffixes.push(format!("{}.{}", suffix, file_name));
     }
+    Ok(suffixes)
 }
@@ -2,48 +2,248 @@
 //! Invoked by calling:
 //! `blobtk filter <args>`
 
-use std::io::ErrorKind;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use rust_htslib::bam::Read;
+use serde::Serialize;
 
 use crate::bam;
+use crate::blobdir;
 use crate::cli;
+use crate::error;
 use crate::fasta;
 use crate::fastq;
-use crate::io;
+use crate::io::{self, FileReport};
+use crate::readset::ReadSet;
+use crate::ubam;
 
 pub use cli::FilterOptions;
 
-/// Execute the `filter` subcommand from `blobtk`.
-/// Pass a list of sequence names and a BAM file to generate
-/// a list of read names and filtered FASTA/FASTQ files.
-pub fn filter(options: &cli::FilterOptions) -> Result<(), anyhow::Error> {
-    let seq_names = io::get_list(&options.list_file);
-    if seq_names.is_empty() {
-        return Ok(());
-    }
-    fasta::subsample(
-        &seq_names,
-        &options.fasta,
-        &options.fasta_out,
-        &options.suffix,
-        &None as &Option<Box<dyn Fn()>>,
-    );
-    if options.bam.is_none() && options.cram.is_none() {
-        return Ok(());
+/// Summary of a `blobtk filter` run, written as JSON with `--report`.
+#[derive(Debug, Default, Serialize)]
+pub struct FilterReport {
+    /// Number of sequences listed
+    pub contigs_requested: usize,
+    /// Number of listed sequences in the BAM/CRAM header
+    pub contigs_found: usize,
+    /// Listed sequences missing from the BAM/CRAM header
+    pub contigs_missing: Vec<String>,
+    /// Reads selected on each sequence, counting each read on the first
+    /// sequence it was selected from (`*` for unmapped reads)
+    pub reads_per_contig: BTreeMap<String, usize>,
+    /// Total number of reads selected
+    pub reads_selected: usize,
+    /// Records written to each output file
+    pub files: Vec<FileReport>,
+}
+
+impl FilterReport {
+    /// Count a selected read against the sequence it was found on.
+    pub fn add_read(&mut self, seq_name: &str) {
+        *self
+            .reads_per_contig
+            .entry(seq_name.to_string())
+            .or_insert(0) += 1;
     }
-    let bam = bam::open_bam(&options.bam, &options.cram, &options.fasta, true);
-    let read_names = bam::reads_from_bam(&seq_names, bam, &None as &Option<Box<dyn Fn()>>);
-    fastq::subsample(
-        &read_names,
+}
+
+fn write_reads<F: Fn()>(
+    seq_names: &HashSet<Vec<u8>>,
+    read_names: &ReadSet,
+    options: &cli::FilterOptions,
+    report: &mut FilterReport,
+    callback: &Option<F>,
+) -> Result<(), error::Error> {
+    report.files.extend(fastq::subsample(
+        read_names,
         &options.fastq1,
         &options.fastq2,
         &options.fastq_out,
         &options.suffix,
-        &None as &Option<Box<dyn Fn()>>,
+        &fastq::PairedLayout::from_options(options),
+        callback,
+    )?);
+    report.files.extend(ubam::subsample(
+        read_names,
+        &options.ubam,
+        &options.ubam_out,
+        &options.fastq_out,
+        &options.suffix,
+        callback,
+    )?);
+    let comments = [format!("pairs={}", options.pairs)];
+    match io::write_list_with_comments(read_names, &comments, &options.read_list) {
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => (),
+        Err(err) => return Err(err.into()),
+        Ok(_) => (),
+    };
+    report
+        .files
+        .extend(bam::write_filtered_bam(options, seq_names, read_names)?);
+    Ok(())
+}
+
+/// Write one set of FASTA/FASTQ files per bin from a single pass over
+/// each input. Bins come from a TSV file or a BlobDir category field.
+pub fn partition<F: Fn()>(
+    options: &cli::FilterOptions,
+    callback: &Option<F>,
+) -> Result<FilterReport, error::Error> {
+    let (bins, seq_bins) = match (&options.partition, &options.partition_field) {
+        (Some(partition), _) => io::get_bins(partition)?,
+        (None, Some(field)) => {
+            blobdir::category_bins(options.blobdir.as_ref().unwrap(), field, &options.filter)?
+        }
+        _ => return Err(error::Error::NotDefined("partition".to_string())),
+    };
+    let mut report = FilterReport {
+        contigs_requested: seq_bins.len(),
+        ..Default::default()
+    };
+    if options.fasta_out {
+        report.files.extend(fasta::partition(
+            &seq_bins,
+            &bins,
+            &options.fasta,
+            &options.suffix,
+            callback,
+        )?);
+    }
+    if options.bam.is_none() && options.cram.is_none() {
+        return Ok(report);
+    }
+    let bam = bam::open_bam_stream(&options.bam, &options.cram, &options.fasta);
+    let read_bins = bam::read_bins_from_bam(
+        &seq_bins,
+        bam,
+        &options.read_filter,
+        &options.pairs,
+        callback,
     );
-    match io::write_list(&read_names, &options.read_list) {
-        Err(err) if err.kind() == ErrorKind::BrokenPipe => return Ok(()),
-        Err(err) => panic!("unable to write read list file: {}", err),
+    report.reads_selected = read_bins.len();
+    if options.fastq_out {
+        report.files.extend(fastq::partition(
+            &read_bins,
+            &bins,
+            &options.fastq1,
+            &options.fastq2,
+            &options.suffix,
+            &fastq::PairedLayout::from_options(options),
+            callback,
+        )?);
+    }
+    match io::write_bins(&read_bins, &bins, &options.read_list) {
+        Err(err) if err.kind() == ErrorKind::BrokenPipe => (),
+        Err(err) => return Err(err.into()),
         Ok(_) => (),
     };
+    Ok(report)
+}
+
+/// Filter FASTA/FASTQ and alignment files to the listed sequences and the
+/// reads aligned to them, calling `callback` as records are processed.
+pub fn filter_reads<F: Fn()>(
+    options: &cli::FilterOptions,
+    callback: &Option<F>,
+) -> Result<FilterReport, error::Error> {
+    if options.partition.is_some() || options.partition_field.is_some() {
+        return partition(options, callback);
+    }
+    let seq_names = match (options.list.to_owned(), &options.blobdir) {
+        (Some(list), _) => list,
+        (None, Some(blobdir)) => blobdir::filtered_identifiers(blobdir, &options.filter)?,
+        (None, None) => io::get_list(&options.list_file),
+    };
+    let mut report = FilterReport {
+        contigs_requested: seq_names.len(),
+        ..Default::default()
+    };
+    if options.invert {
+        let bam = bam::open_bam_stream(&options.bam, &options.cram, &options.fasta);
+        report.files.extend(fasta::subsample(
+            &bam::invert_seq_names(&seq_names, bam.header()),
+            &options.region,
+            &options.fasta,
+            &options.fasta_out,
+            &options.revcomp,
+            &options.suffix,
+            callback,
+        )?);
+        let read_names = bam::reads_from_bam_inverted(
+            &seq_names,
+            bam,
+            &options.read_filter,
+            &options.pairs,
+            &mut report,
+            callback,
+        );
+        write_reads(&seq_names, &read_names, options, &mut report, callback)?;
+        return Ok(report);
+    }
+    report.files.extend(fasta::subsample(
+        &seq_names,
+        &options.region,
+        &options.fasta,
+        &options.fasta_out,
+        &options.revcomp,
+        &options.suffix,
+        callback,
+    )?);
+    if seq_names.is_empty() || (options.bam.is_none() && options.cram.is_none()) {
+        return Ok(report);
+    }
+    let read_names = if options.stream {
+        let bam = bam::open_bam_stream(&options.bam, &options.cram, &options.fasta);
+        bam::reads_from_bam_stream(
+            &seq_names,
+            bam,
+            &options.read_filter,
+            &options.pairs,
+            &mut report,
+            callback,
+        )
+    } else {
+        let bam = bam::open_bam(
+            &options.bam,
+            &options.cram,
+            &options.fasta,
+            &options.index_dir,
+            true,
+        );
+        bam::reads_from_bam(
+            &seq_names,
+            bam,
+            &options.read_filter,
+            &options.pairs,
+            &mut report,
+            callback,
+        )
+    };
+    write_reads(&seq_names, &read_names, options, &mut report, callback)?;
+    Ok(report)
+}
+
+/// Write a filter report as JSON.
+pub fn write_report(
+    report: &FilterReport,
+    report_path: &Option<PathBuf>,
+) -> Result<(), error::Error> {
+    if report_path.is_none() {
+        return Ok(());
+    }
+    let mut writer = io::get_writer(report_path);
+    serde_json::to_writer_pretty(&mut writer, report)?;
+    writeln!(&mut writer)?;
+    Ok(())
+}
+
+/// Execute the `filter` subcommand from `blobtk`.
+/// Pass a list of sequence names and a BAM file to generate
+/// a list of read names and filtered FASTA/FASTQ files.
+pub fn filter(options: &cli::FilterOptions) -> Result<(), anyhow::Error> {
+    let report = filter_reads(options, &None as &Option<Box<dyn Fn()>>)?;
+    write_report(&report, &options.report)?;
     Ok(())
 }
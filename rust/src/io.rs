@@ -1,13 +1,19 @@
 extern crate atty;
-use std::collections::HashSet;
-use std::io::{self, BufRead, BufWriter, Result, Write};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, BufReader, BufWriter, Result, Write};
 use std::path::{Path, PathBuf};
 
 use std::fs::{create_dir_all, File};
 
-use flate2::write;
-use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use rust_htslib::bgzf;
+use rust_htslib::tpool::ThreadPool;
+use serde::Serialize;
 use std::ffi::OsStr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::cli::CompressionOptions;
+use crate::readset::ReadSet;
 
 fn read_stdin() -> Vec<Vec<u8>> {
     let stdin = io::stdin();
@@ -48,30 +54,133 @@ fn read_file(file_path: &PathBuf) -> Vec<Vec<u8>> {
     output
 }
 
+/// Number of records and bases written to an output file.
+#[derive(Debug, Default, Serialize)]
+pub struct FileReport {
+    pub path: String,
+    pub records: usize,
+    /// Read pairs with a mate in this file (0 for unpaired output)
+    pub pairs: usize,
+    pub bases: usize,
+}
+
+impl FileReport {
+    pub fn new(path: &Path) -> FileReport {
+        FileReport {
+            path: path.display().to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Count a record of `length` bases.
+    pub fn add(&mut self, length: usize) {
+        self.records += 1;
+        self.bases += length;
+    }
+}
+
 pub fn get_list(file_path: &Option<PathBuf>) -> HashSet<Vec<u8>> {
     let list = match file_path {
         None => vec![],
         Some(p) if p == Path::new("-") => read_stdin(),
         Some(_) => read_file(file_path.as_ref().unwrap()),
     };
-    HashSet::from_iter(list)
+    HashSet::from_iter(list.into_iter().filter(|entry| !entry.starts_with(b"#")))
 }
 
-pub fn get_file_writer(file_path: &PathBuf) -> Box<dyn Write> {
-    let file = match File::create(file_path) {
-        Err(why) => panic!("couldn't open {}: {}", file_path.display(), why),
-        Ok(file) => file,
-    };
+/// Read a two-column sequence to bin mapping. Returns the bin names in the
+/// order first seen and the bin index for each sequence.
+pub fn get_bins(file_path: &PathBuf) -> Result<(Vec<String>, HashMap<Vec<u8>, usize>)> {
+    let mut bins: Vec<String> = vec![];
+    let mut bin_indices: HashMap<String, usize> = HashMap::new();
+    let mut seq_bins = HashMap::new();
+    for (i, line) in get_reader(file_path)?.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut columns = line.split('\t');
+        let (seq_name, bin) = match (columns.next(), columns.next()) {
+            (Some(seq_name), Some(bin)) if !bin.is_empty() => (seq_name, bin),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} line {}: expected two columns",
+                        file_path.display(),
+                        i + 1
+                    ),
+                ))
+            }
+        };
+        let next_index = bins.len();
+        let index = *bin_indices.entry(bin.to_string()).or_insert_with(|| {
+            bins.push(bin.to_string());
+            next_index
+        });
+        seq_bins.insert(seq_name.as_bytes().to_vec(), index);
+    }
+    Ok((bins, seq_bins))
+}
 
-    let writer: Box<dyn Write> = if file_path.extension() == Some(OsStr::new("gz")) {
-        Box::new(BufWriter::with_capacity(
+/// Open a plain or gzipped (including bgzipped) file for reading,
+/// or STDIN when the path is `-`.
+pub fn get_reader(file_path: &PathBuf) -> Result<Box<dyn BufRead>> {
+    if file_path == Path::new("-") {
+        return Ok(Box::new(BufReader::new(io::stdin().lock())));
+    }
+    let file = File::open(file_path)?;
+    let reader: Box<dyn BufRead> = if file_path.extension() == Some(OsStr::new("gz")) {
+        Box::new(BufReader::with_capacity(
             128 * 1024,
-            write::GzEncoder::new(file, Compression::default()),
+            MultiGzDecoder::new(file),
         ))
     } else {
-        Box::new(BufWriter::with_capacity(128 * 1024, file))
+        Box::new(BufReader::with_capacity(128 * 1024, file))
     };
-    writer
+    Ok(reader)
+}
+
+static COMPRESSION_LEVEL: AtomicU32 = AtomicU32::new(6);
+static COMPRESSION_THREADS: AtomicUsize = AtomicUsize::new(1);
+
+/// Set the compression level and number of threads used for every
+/// `.gz` file opened by `get_writer`.
+pub fn set_compression(options: &CompressionOptions) {
+    COMPRESSION_LEVEL.store(options.compression_level, Ordering::Relaxed);
+    COMPRESSION_THREADS.store(options.compression_threads, Ordering::Relaxed);
+}
+
+/// Open a block-gzipped (BGZF) writer, so output can be read and
+/// indexed by samtools/htslib tools.
+fn get_bgzf_writer(file_path: &PathBuf) -> Box<dyn Write> {
+    let level = match COMPRESSION_LEVEL.load(Ordering::Relaxed) {
+        0 => bgzf::CompressionLevel::Uncompressed,
+        level => bgzf::CompressionLevel::Level(level.min(9) as i8),
+    };
+    let mut writer = match bgzf::Writer::from_path_with_level(file_path, level) {
+        Err(why) => panic!("couldn't open {}: {}", file_path.display(), why),
+        Ok(writer) => writer,
+    };
+    let threads = COMPRESSION_THREADS.load(Ordering::Relaxed);
+    if threads > 1 {
+        let thread_pool = ThreadPool::new(threads as u32).expect("unable to start thread pool");
+        writer
+            .set_thread_pool(&thread_pool)
+            .expect("unable to use thread pool");
+    }
+    Box::new(BufWriter::with_capacity(128 * 1024, writer))
+}
+
+pub fn get_file_writer(file_path: &PathBuf) -> Box<dyn Write> {
+    if file_path.extension() == Some(OsStr::new("gz")) {
+        return get_bgzf_writer(file_path);
+    }
+    let file = match File::create(file_path) {
+        Err(why) => panic!("couldn't open {}: {}", file_path.display(), why),
+        Ok(file) => file,
+    };
+    Box::new(BufWriter::with_capacity(128 * 1024, file))
 }
 
 pub fn get_writer(file_path: &Option<PathBuf>) -> Box<dyn Write> {
@@ -86,10 +195,44 @@ pub fn get_writer(file_path: &Option<PathBuf>) -> Box<dyn Write> {
     writer
 }
 
-pub fn write_list(entries: &HashSet<Vec<u8>>, file_path: &Option<PathBuf>) -> Result<()> {
+pub fn write_list(entries: &ReadSet, file_path: &Option<PathBuf>) -> Result<()> {
+    write_list_with_comments(entries, &[], file_path)
+}
+
+/// Write a list preceded by `#` comment lines, which `get_list` skips.
+pub fn write_list_with_comments(
+    entries: &ReadSet,
+    comments: &[String],
+    file_path: &Option<PathBuf>,
+) -> Result<()> {
     let mut writer = get_writer(file_path);
+    for comment in comments.iter() {
+        writeln!(&mut writer, "#{}", comment)?;
+    }
     for line in entries.iter() {
-        writeln!(&mut writer, "{}", String::from_utf8(line.to_vec()).unwrap())?;
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Write a two-column list of entries and the names of their bins, sorted
+/// by bin and then by entry.
+pub fn write_bins(
+    entries: &HashMap<Vec<u8>, usize>,
+    bins: &[String],
+    file_path: &Option<PathBuf>,
+) -> Result<()> {
+    let mut writer = get_writer(file_path);
+    let mut entries: Vec<(&Vec<u8>, &usize)> = entries.iter().collect();
+    entries.sort_by_key(|(entry, bin)| (**bin, *entry));
+    for (entry, bin) in entries {
+        writeln!(
+            &mut writer,
+            "{}\t{}",
+            String::from_utf8(entry.to_vec()).unwrap(),
+            bins[*bin]
+        )?;
     }
     Ok(())
 }
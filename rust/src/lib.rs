@@ -1,15 +1,24 @@
 //! `blobtk` is a set of core command line utilities and python bindings for
 //! processing common file formats used by [BlobToolKit](https://blobtoolkit.genomehubs.org).
 
+/// Add fields to a BlobDir.
+pub mod add;
+
 /// Functions for processing BAM files.
 pub mod bam;
 
+/// Write coverage tracks in bigWig format.
+pub mod bigwig;
+
 /// Functions for processing a BlobDir.
 pub mod blobdir;
 
 /// The BlobTk Command Line Interface.
 pub mod cli;
 
+/// Create a new BlobDir.
+pub mod create;
+
 /// Summarise windowed coverage depth.
 pub mod depth;
 
@@ -28,14 +37,23 @@ pub mod filter;
 /// Functions for file/terminal IO.
 pub mod io;
 
+/// Functions for processing PAF files.
+pub mod paf;
+
 /// Generate a plot.
 pub mod plot;
 
 /// Python bindings.
 pub mod python;
 
+/// Compact sets of read names.
+pub mod readset;
+
 /// Parse and subset a taxonomy.
 pub mod taxonomy;
 
+/// Functions for processing unaligned BAM files.
+pub mod ubam;
+
 /// Utility functions.
 pub mod utils;
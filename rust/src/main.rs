@@ -2,14 +2,19 @@ use std::process;
 
 use anyhow;
 
+use blobtk::add;
 use blobtk::cli;
+use blobtk::create;
 use blobtk::depth;
 use blobtk::filter;
+use blobtk::io;
 use blobtk::plot;
 use blobtk::taxonomy;
 
 fn cmd(args: cli::Arguments) -> Result<(), anyhow::Error> {
     match args.cmd {
+        cli::SubCommand::Add(options) => add::add(&options)?,
+        cli::SubCommand::Create(options) => create::create(&options)?,
         cli::SubCommand::Filter(options) => filter::filter(&options)?,
         cli::SubCommand::Depth(options) => depth::depth(&options)?,
         cli::SubCommand::Plot(options) => plot::plot(&options)?,
@@ -19,6 +24,7 @@ fn cmd(args: cli::Arguments) -> Result<(), anyhow::Error> {
 }
 fn main() {
     let args = cli::parse();
+    io::set_compression(&args.compression);
     if let Err(e) = cmd(args) {
         eprintln!("ERROR: {e}");
         process::exit(1);
@@ -0,0 +1,299 @@
+//!
+//! Reads pairwise alignments in PAF format (as written by minimap2) and
+//! collects the target intervals needed to calculate coverage depth.
+
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+use rust_htslib::htslib;
+
+use crate::bam::{
+    binned_depth, library_labels, write_depth, BinnedCov, DepthAccumulator, SeqCoverage, SpanSweep,
+};
+use crate::cli::{DepthOptions, ReadFilter};
+use crate::error;
+use crate::io::get_reader;
+
+/// A single alignment on a target sequence.
+#[derive(Clone, Copy, Debug)]
+struct PafHit {
+    start: usize,
+    end: usize,
+    primary: bool,
+}
+
+/// Alignments from a PAF file, grouped by target sequence.
+#[derive(Debug, Default)]
+pub struct PafAlignments {
+    seq_lengths: IndexMap<String, usize>,
+    hits: HashMap<String, Vec<PafHit>>,
+}
+
+impl ReadFilter {
+    /// Check whether a PAF line passes the mapping quality, aligned length
+    /// and identity filters. Secondary alignments (`tp:A:S`) are treated as
+    /// having the secondary flag set, PAF has no other flags to test. The
+    /// aligned length is the alignment block length (column 11), which also
+    /// counts gaps, rather than the M/=/X bases counted for BAM records.
+    fn keep_paf(&self, mapq: u8, matches: usize, block_length: usize, secondary: bool) -> bool {
+        let flags = if secondary {
+            htslib::BAM_FSECONDARY as u16
+        } else {
+            0
+        };
+        if flags & self.include_flags != self.include_flags || flags & self.exclude_flags != 0 {
+            return false;
+        }
+        if mapq < self.min_mapq || block_length < self.min_aligned_length {
+            return false;
+        }
+        if self.min_identity > 0.0
+            && (block_length == 0 || (matches as f64 / block_length as f64) < self.min_identity)
+        {
+            return false;
+        }
+        true
+    }
+}
+
+fn parse_column<T: std::str::FromStr>(
+    columns: &[&str],
+    index: usize,
+    path: &Path,
+    line_number: usize,
+) -> Result<T, error::Error> {
+    columns
+        .get(index)
+        .and_then(|value| value.parse::<T>().ok())
+        .ok_or_else(|| {
+            error::Error::ParseError(format!(
+                "{} line {}: invalid PAF column {}",
+                path.display(),
+                line_number,
+                index + 1
+            ))
+        })
+}
+
+impl PafAlignments {
+    /// Target sequence names and lengths, in the order first seen.
+    pub fn seq_lengths(&self) -> &IndexMap<String, usize> {
+        &self.seq_lengths
+    }
+
+    /// Add the depth of all kept alignments on a sequence.
+    pub(crate) fn add_depths(&self, seq_name: &str, depths: &mut DepthAccumulator) {
+        let mut spans: Vec<(usize, usize)> = self
+            .hits
+            .get(seq_name)
+            .into_iter()
+            .flatten()
+            .map(|hit| (hit.start, hit.end))
+            .collect();
+        spans.sort_unstable();
+        let mut sweep = SpanSweep::new();
+        for (start, end) in spans {
+            sweep.push(start, end, depths);
+        }
+        sweep.finish(depths);
+    }
+
+    /// Target start positions of kept primary alignments on a sequence.
+    pub fn read_starts(&self, seq_name: &str) -> Vec<usize> {
+        self.hits
+            .get(seq_name)
+            .into_iter()
+            .flatten()
+            .filter(|hit| hit.primary)
+            .map(|hit| hit.start)
+            .collect()
+    }
+}
+
+/// Read a plain or gzipped PAF file, keeping alignments to the listed
+/// sequences (or all targets when the list is empty) that pass the read filter.
+/// Target lengths are kept for every listed target with a PAF line, so
+/// targets whose alignments are all filtered out still have zero depth.
+pub fn read_paf(
+    paf_path: &PathBuf,
+    seq_names: &HashSet<Vec<u8>>,
+    read_filter: &ReadFilter,
+) -> Result<PafAlignments, error::Error> {
+    let reader = get_reader(paf_path)?;
+    let mut paf = PafAlignments::default();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let columns: Vec<&str> = line.split('\t').collect();
+        if columns.len() < 12 {
+            return Err(error::Error::ParseError(format!(
+                "{} line {}: expected at least 12 PAF columns",
+                paf_path.display(),
+                i + 1
+            )));
+        }
+        let seq_name = columns[5];
+        if !seq_names.is_empty() && !seq_names.contains(seq_name.as_bytes()) {
+            continue;
+        }
+        let length: usize = parse_column(&columns, 6, paf_path, i + 1)?;
+        match paf.seq_lengths.get(seq_name) {
+            Some(known) if *known != length => {
+                return Err(error::Error::MismatchedHeaders(format!(
+                    "{} has lengths {} and {} in {}",
+                    seq_name,
+                    known,
+                    length,
+                    paf_path.display()
+                )))
+            }
+            Some(_) => (),
+            None => {
+                paf.seq_lengths.insert(seq_name.to_string(), length);
+            }
+        }
+        let start: usize = parse_column(&columns, 7, paf_path, i + 1)?;
+        let end: usize = parse_column(&columns, 8, paf_path, i + 1)?;
+        let matches: usize = parse_column(&columns, 9, paf_path, i + 1)?;
+        let block_length: usize = parse_column(&columns, 10, paf_path, i + 1)?;
+        let mapq: u8 = parse_column(&columns, 11, paf_path, i + 1)?;
+        let alignment_type = columns[12..]
+            .iter()
+            .find_map(|tag| tag.strip_prefix("tp:A:"))
+            .unwrap_or("P");
+        let secondary = alignment_type == "S";
+        if !read_filter.keep_paf(mapq, matches, block_length, secondary) {
+            continue;
+        }
+        paf.hits
+            .entry(seq_name.to_string())
+            .or_default()
+            .push(PafHit {
+                start,
+                end,
+                primary: alignment_type == "P",
+            });
+    }
+    Ok(paf)
+}
+
+/// Read each PAF file passed to `blobtk depth` and pair it with a library label.
+pub fn open_libraries(
+    options: &DepthOptions,
+    seq_names: &HashSet<Vec<u8>>,
+) -> Result<Vec<(String, PafAlignments)>, error::Error> {
+    if options.paf.is_empty() {
+        return Err(error::Error::NotDefined("paf".to_string()));
+    }
+    if !options.bam.is_empty() || !options.cram.is_empty() {
+        return Err(error::Error::InvalidOption(
+            "PAF input cannot be combined with BAM/CRAM files".to_string(),
+        ));
+    }
+    let labels = library_labels(options, options.paf.iter())?;
+    let mut libraries = vec![];
+    for (label, paf_path) in labels.into_iter().zip(options.paf.iter()) {
+        libraries.push((label, read_paf(paf_path, seq_names, &options.read_filter)?));
+    }
+    let mut missing: Vec<String> = seq_names
+        .iter()
+        .map(|seq_name| String::from_utf8_lossy(seq_name).to_string())
+        .filter(|seq_name| {
+            libraries
+                .iter()
+                .all(|(_, paf)| !paf.seq_lengths().contains_key(seq_name))
+        })
+        .collect();
+    missing.sort();
+    for seq_name in missing.iter() {
+        eprintln!("Sequence {:?} not found in PAF files", seq_name)
+    }
+    Ok(libraries)
+}
+
+/// Combine target lengths from all libraries. PAF files only list targets
+/// with alignments so sequences are kept in the order first seen, but
+/// lengths must agree between files.
+fn library_seq_lengths(
+    libraries: &[(String, PafAlignments)],
+) -> Result<IndexMap<String, usize>, error::Error> {
+    let mut seq_lengths: IndexMap<String, usize> = IndexMap::new();
+    for (label, paf) in libraries.iter() {
+        for (seq_name, length) in paf.seq_lengths() {
+            match seq_lengths.get(seq_name) {
+                Some(known) if known != length => {
+                    return Err(error::Error::MismatchedHeaders(format!(
+                        "{} has length {} in {} but {} in an earlier file",
+                        seq_name, length, label, known
+                    )))
+                }
+                Some(_) => (),
+                None => {
+                    seq_lengths.insert(seq_name.clone(), *length);
+                }
+            }
+        }
+    }
+    Ok(seq_lengths)
+}
+
+fn library_coverages(
+    libraries: &[(String, PafAlignments)],
+    seq_name: &str,
+    length: usize,
+    options: &DepthOptions,
+) -> Vec<SeqCoverage> {
+    libraries
+        .iter()
+        .map(|(_, paf)| {
+            let read_starts = if options.stats {
+                Some(paf.read_starts(seq_name))
+            } else {
+                None
+            };
+            let mut depths = DepthAccumulator::new(length, options);
+            paf.add_depths(seq_name, &mut depths);
+            depths.finish(read_starts)
+        })
+        .collect()
+}
+
+pub fn get_bed_file<F: Fn()>(
+    libraries: Vec<(String, PafAlignments)>,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<(), error::Error> {
+    let seq_lengths = library_seq_lengths(&libraries)?;
+    let labels: Vec<String> = libraries.iter().map(|(label, _)| label.clone()).collect();
+    write_depth(
+        &seq_lengths,
+        &labels,
+        libraries.as_slice(),
+        || libraries.as_slice(),
+        |libraries, seq_name, length| library_coverages(libraries, seq_name, length, options),
+        options,
+        callback,
+    )
+}
+
+pub fn get_depth<F: Fn()>(
+    libraries: Vec<(String, PafAlignments)>,
+    options: &DepthOptions,
+    callback: &Option<F>,
+) -> Result<Vec<BinnedCov>, error::Error> {
+    let seq_lengths = library_seq_lengths(&libraries)?;
+    let labels: Vec<String> = libraries.iter().map(|(label, _)| label.clone()).collect();
+    binned_depth(
+        &seq_lengths,
+        &labels,
+        libraries.as_slice(),
+        || libraries.as_slice(),
+        |libraries, seq_name, length| library_coverages(libraries, seq_name, length, options),
+        options,
+        callback,
+    )
+}
@@ -111,8 +111,8 @@ pub fn plot_snail(meta: &blobdir::Meta, options: &cli::PlotOptions) -> Result<()
     let id = meta.id.clone();
     let record_type = meta.record_type.clone();
 
-    let filters = blobdir::parse_filters(&options, None);
-    let wanted_indices = blobdir::set_filters(filters, &meta, &options.blobdir);
+    let filters = blobdir::parse_filters(&options, None)?;
+    let wanted_indices = blobdir::set_filters(filters, &meta, &options.blobdir)?;
 
     let gc_filtered = blobdir::apply_filter_float(&gc_values, &wanted_indices);
     let n_filtered = match n_values {
@@ -293,8 +293,8 @@ fn set_blob_data(
         &options.cat_count,
         &palette,
     );
-    let filters = blobdir::parse_filters(&options, Some(&plot_meta));
-    let wanted_indices = blobdir::set_filters(filters, &meta, &options.blobdir);
+    let filters = blobdir::parse_filters(&options, Some(&plot_meta))?;
+    let wanted_indices = blobdir::set_filters(filters, &meta, &options.blobdir)?;
     let z = blobdir::apply_filter_float(&plot_values["z"], &wanted_indices);
     let filtered_cat_values = blobdir::apply_filter_cat_tuple(&cat_values, &wanted_indices);
     let (cat_order, cat_indices) = if wanted_indices.len() < plot_values["x"].len() {
@@ -401,8 +401,8 @@ pub fn plot_cumulative(
     // let id = meta.id.clone();
     // let record_type = meta.record_type.clone();
 
-    let filters = blobdir::parse_filters(&options, None);
-    let wanted_indices = blobdir::set_filters(filters, &meta, &options.blobdir);
+    let filters = blobdir::parse_filters(&options, None)?;
+    let wanted_indices = blobdir::set_filters(filters, &meta, &options.blobdir)?;
 
     let cumulative_data = CumulativeData {
         values: blobdir::apply_filter_float(&plot_values["z"], &wanted_indices),
@@ -3,35 +3,106 @@ use std::collections::HashSet;
 use std::path::PathBuf;
 
 use crate::bam::{self, BinnedCov};
-use crate::cli::DepthOptions;
+use crate::cli::{DepthEngine, DepthOptions, ReadFilter};
 use crate::io;
-use crate::python::utils::{extract_to_option_list, extract_to_option_pathbuf, extract_to_usize};
+use crate::paf;
+use crate::python::utils::{
+    extract_read_filter, extract_to_bool, extract_to_default_string, extract_to_default_usize,
+    extract_to_f64_vec, extract_to_option_f64, extract_to_option_list, extract_to_option_pathbuf,
+    extract_to_pathbuf_vec, extract_to_string_vec, extract_to_usize, extract_value_enum,
+    set_compression,
+};
 use pyo3::prelude::*;
 
 #[pymethods]
 impl DepthOptions {
     #[new]
+    #[pyo3(signature = (
+        bin_size,
+        window_size = vec![],
+        window_step = None,
+        list = None,
+        list_file = None,
+        bam = vec![],
+        cram = vec![],
+        paf = vec![],
+        stream = false,
+        index_dir = None,
+        label = vec![],
+        fasta = None,
+        bed = None,
+        bedgraph = None,
+        bigwig = None,
+        quantized = None,
+        quantize = String::from("0:1:5:150:"),
+        stats = false,
+        thresholds = vec![1, 5, 10],
+        threads = 1,
+        engine = None,
+        read_filter = ReadFilter::default(),
+    ))]
+    #[allow(clippy::too_many_arguments)]
     fn new(
         bin_size: usize,
+        window_size: Vec<f64>,
+        window_step: Option<f64>,
         list: Option<HashSet<Vec<u8>>>,
         list_file: Option<PathBuf>,
-        bam: Option<PathBuf>,
-        cram: Option<PathBuf>,
+        bam: Vec<PathBuf>,
+        cram: Vec<PathBuf>,
+        paf: Vec<PathBuf>,
+        stream: bool,
+        index_dir: Option<PathBuf>,
+        label: Vec<String>,
         fasta: Option<PathBuf>,
         bed: Option<PathBuf>,
+        bedgraph: Option<PathBuf>,
+        bigwig: Option<PathBuf>,
+        quantized: Option<PathBuf>,
+        quantize: String,
+        stats: bool,
+        thresholds: Vec<u32>,
+        threads: usize,
+        engine: Option<DepthEngine>,
+        read_filter: ReadFilter,
     ) -> Self {
+        let engine = engine.unwrap_or(default_engine(stream));
         DepthOptions {
             list,
             list_file,
             bam,
             cram,
+            paf,
+            stream,
+            index_dir,
+            label,
             fasta,
             bin_size,
+            window_size,
+            window_step,
             bed,
+            bedgraph,
+            bigwig,
+            quantized,
+            quantize,
+            stats,
+            thresholds,
+            threads,
+            engine,
+            read_filter,
         }
     }
 }
 
+/// Streamed input has no index for pileups, so it defaults to the CIGAR engine.
+fn default_engine(stream: bool) -> DepthEngine {
+    if stream {
+        DepthEngine::Cigar
+    } else {
+        DepthEngine::Pileup
+    }
+}
+
 #[pyfunction]
 pub fn bam_to_bed_with_options(options: &DepthOptions, py: Python) -> PyResult<usize> {
     let seq_names = match options.list.to_owned() {
@@ -44,13 +115,33 @@ pub fn bam_to_bed_with_options(options: &DepthOptions, py: Python) -> PyResult<u
     let ctrlc_wrapper = || {
         py.check_signals().unwrap();
     };
-    let bam = bam::open_bam(&options.bam, &options.cram, &options.fasta, true);
-    bam::get_bed_file(bam, &seq_names, options, &Some(Box::new(ctrlc_wrapper)));
+    if !options.paf.is_empty() {
+        let libraries = paf::open_libraries(options, &seq_names)?;
+        paf::get_bed_file(libraries, options, &Some(Box::new(ctrlc_wrapper)))?;
+        return Ok(1);
+    }
+    if options.stream {
+        let libraries = bam::open_stream_libraries(options)?;
+        bam::get_stream_bed_file(
+            libraries,
+            &seq_names,
+            options,
+            &Some(Box::new(ctrlc_wrapper)),
+        )?;
+        return Ok(1);
+    }
+    let libraries = bam::open_libraries(options)?;
+    bam::get_bed_file(
+        libraries,
+        &seq_names,
+        options,
+        &Some(Box::new(ctrlc_wrapper)),
+    )?;
     Ok(1)
 }
 
 #[pyfunction]
-pub fn bam_to_depth_with_options(options: &DepthOptions, py: Python) -> Vec<BinnedCov> {
+pub fn bam_to_depth_with_options(options: &DepthOptions, py: Python) -> PyResult<Vec<BinnedCov>> {
     let seq_names = match options.list.to_owned() {
         Some(value) => value,
         _ => {
@@ -61,34 +152,93 @@ pub fn bam_to_depth_with_options(options: &DepthOptions, py: Python) -> Vec<Binn
     let ctrlc_wrapper = || {
         py.check_signals().unwrap();
     };
-    let bam = bam::open_bam(&options.bam, &options.cram, &options.fasta, true);
-    bam::get_depth(bam, &seq_names, options, &Some(Box::new(ctrlc_wrapper)))
+    if !options.paf.is_empty() {
+        let libraries = paf::open_libraries(options, &seq_names)?;
+        let binned_covs = paf::get_depth(libraries, options, &Some(Box::new(ctrlc_wrapper)))?;
+        return Ok(binned_covs);
+    }
+    if options.stream {
+        let libraries = bam::open_stream_libraries(options)?;
+        let binned_covs = bam::get_stream_depth(
+            libraries,
+            &seq_names,
+            options,
+            &Some(Box::new(ctrlc_wrapper)),
+        )?;
+        return Ok(binned_covs);
+    }
+    let libraries = bam::open_libraries(options)?;
+    let binned_covs = bam::get_depth(
+        libraries,
+        &seq_names,
+        options,
+        &Some(Box::new(ctrlc_wrapper)),
+    )?;
+    Ok(binned_covs)
 }
 
-fn convert_hashmap_to_options(py: Python<'_>, map: HashMap<String, PyObject>) -> DepthOptions {
+fn convert_hashmap_to_options(
+    py: Python<'_>,
+    map: HashMap<String, PyObject>,
+) -> PyResult<DepthOptions> {
+    set_compression(py, &map);
     let list = extract_to_option_list(py, &map, "list");
     let list_file = extract_to_option_pathbuf(py, &map, "list_file");
-    let bam = extract_to_option_pathbuf(py, &map, "bam");
-    let cram = extract_to_option_pathbuf(py, &map, "cram");
+    let bam = extract_to_pathbuf_vec(py, &map, "bam")?;
+    let cram = extract_to_pathbuf_vec(py, &map, "cram")?;
+    let paf = extract_to_pathbuf_vec(py, &map, "paf")?;
+    let stream = extract_to_bool(py, &map, "stream");
+    let index_dir = extract_to_option_pathbuf(py, &map, "index_dir");
+    let label = extract_to_string_vec(py, &map, "label")?;
     let fasta = extract_to_option_pathbuf(py, &map, "fasta");
     let bed = extract_to_option_pathbuf(py, &map, "bed");
+    let bedgraph = extract_to_option_pathbuf(py, &map, "bedgraph");
+    let bigwig = extract_to_option_pathbuf(py, &map, "bigwig");
+    let quantized = extract_to_option_pathbuf(py, &map, "quantized");
+    let quantize = extract_to_default_string(py, &map, "quantize", "0:1:5:150:");
     let bin_size = extract_to_usize(py, &map, "bin_size");
-    DepthOptions {
+    let stats = extract_to_bool(py, &map, "stats");
+    let thresholds = match map.get("thresholds") {
+        Some(value) => value.extract::<Vec<u32>>(py)?,
+        None => vec![1, 5, 10],
+    };
+    let threads = extract_to_default_usize(py, &map, "threads", 1);
+    let engine =
+        extract_value_enum::<DepthEngine>(py, &map, "engine")?.unwrap_or(default_engine(stream));
+    let read_filter = extract_read_filter(py, &map)?;
+    let window_size = extract_to_f64_vec(py, &map, "window_size")?;
+    let window_step = extract_to_option_f64(py, &map, "window_step")?;
+    Ok(DepthOptions {
         bin_size,
+        window_size,
+        window_step,
         list,
         list_file,
         bam,
         cram,
+        paf,
+        stream,
+        index_dir,
+        label,
         fasta,
         bed,
-    }
+        bedgraph,
+        bigwig,
+        quantized,
+        quantize,
+        stats,
+        thresholds,
+        threads,
+        engine,
+        read_filter,
+    })
 }
 
 #[pyfunction]
 #[pyo3(signature = (**kwds))]
 pub fn bam_to_bed(py: Python<'_>, kwds: Option<HashMap<String, PyObject>>) -> PyResult<()> {
     let options = match kwds {
-        Some(map) => convert_hashmap_to_options(py, map),
+        Some(map) => convert_hashmap_to_options(py, map)?,
         None => panic!["No arguments provided"],
     };
     bam_to_bed_with_options(&options, py)?;
@@ -97,9 +247,12 @@ pub fn bam_to_bed(py: Python<'_>, kwds: Option<HashMap<String, PyObject>>) -> Py
 
 #[pyfunction]
 #[pyo3(signature = (**kwds))]
-pub fn bam_to_depth(py: Python<'_>, kwds: Option<HashMap<String, PyObject>>) -> Vec<BinnedCov> {
+pub fn bam_to_depth(
+    py: Python<'_>,
+    kwds: Option<HashMap<String, PyObject>>,
+) -> PyResult<Vec<BinnedCov>> {
     let options = match kwds {
-        Some(map) => convert_hashmap_to_options(py, map),
+        Some(map) => convert_hashmap_to_options(py, map)?,
         None => panic!["No arguments provided"],
     };
     bam_to_depth_with_options(&options, py)
@@ -2,19 +2,49 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::path::PathBuf;
 
-use crate::bam;
-use crate::cli::FilterOptions;
-use crate::fasta;
-use crate::fastq;
-use crate::io;
+use crate::cli::{FilterOptions, PairPolicy, ReadFilter};
+use crate::filter;
 use crate::python::utils::{
-    extract_to_bool, extract_to_default_string, extract_to_option_list, extract_to_option_pathbuf,
+    extract_read_filter, extract_to_bool, extract_to_default_string, extract_to_option_list,
+    extract_to_option_pathbuf, extract_to_option_string, extract_to_string_vec, extract_value_enum,
+    set_compression,
 };
 use pyo3::prelude::*;
 
 #[pymethods]
 impl FilterOptions {
     #[new]
+    #[pyo3(signature = (
+        suffix,
+        fasta_out,
+        fastq_out,
+        list = None,
+        list_file = None,
+        blobdir = None,
+        filter = vec![],
+        partition = None,
+        partition_field = None,
+        bam = None,
+        cram = None,
+        pairs = PairPolicy::Either,
+        invert = false,
+        stream = false,
+        index_dir = None,
+        fasta = None,
+        region = vec![],
+        revcomp = false,
+        fastq1 = None,
+        fastq2 = None,
+        interleaved = false,
+        interleaved_out = false,
+        repair = false,
+        ubam = None,
+        ubam_out = false,
+        read_list = None,
+        bam_out = None,
+        report = None,
+        read_filter = ReadFilter::default(),
+    ))]
     #[allow(clippy::too_many_arguments)]
     fn new(
         suffix: String,
@@ -22,12 +52,30 @@ impl FilterOptions {
         fastq_out: bool,
         list: Option<HashSet<Vec<u8>>>,
         list_file: Option<PathBuf>,
+        blobdir: Option<PathBuf>,
+        filter: Vec<String>,
+        partition: Option<PathBuf>,
+        partition_field: Option<String>,
         bam: Option<PathBuf>,
         cram: Option<PathBuf>,
+        pairs: PairPolicy,
+        invert: bool,
+        stream: bool,
+        index_dir: Option<PathBuf>,
         fasta: Option<PathBuf>,
+        region: Vec<String>,
+        revcomp: bool,
         fastq1: Option<PathBuf>,
         fastq2: Option<PathBuf>,
+        interleaved: bool,
+        interleaved_out: bool,
+        repair: bool,
+        ubam: Option<PathBuf>,
+        ubam_out: bool,
         read_list: Option<PathBuf>,
+        bam_out: Option<PathBuf>,
+        report: Option<PathBuf>,
+        read_filter: ReadFilter,
     ) -> Self {
         FilterOptions {
             suffix,
@@ -35,12 +83,30 @@ impl FilterOptions {
             fastq_out,
             list,
             list_file,
+            blobdir,
+            filter,
+            partition,
+            partition_field,
             bam,
             cram,
+            pairs,
+            invert,
+            stream,
+            index_dir,
             fasta,
+            region,
+            revcomp,
             fastq1,
             fastq2,
+            interleaved,
+            interleaved_out,
+            repair,
+            ubam,
+            ubam_out,
             read_list,
+            bam_out,
+            report,
+            read_filter,
         }
     }
 }
@@ -50,73 +116,83 @@ pub fn fastx_with_options(options: &FilterOptions, py: Python) -> PyResult<usize
     let ctrlc_wrapper = || {
         py.check_signals().unwrap();
     };
-
-    let seq_names = match options.list.to_owned() {
-        Some(value) => value,
-        _ => {
-            let value = options.list_file.to_owned();
-            io::get_list(&value)
-        }
-    };
-    if seq_names.is_empty() {
-        return Ok(0);
-    }
-    fasta::subsample(
-        &seq_names,
-        &options.fasta,
-        &options.fasta_out,
-        &options.suffix,
-        &Some(Box::new(ctrlc_wrapper)),
-    );
-    if options.bam.is_none() && options.cram.is_none() {
-        return Ok(0);
-    }
-    let bam = bam::open_bam(&options.bam, &options.cram, &options.fasta, true);
-    let read_names = bam::reads_from_bam(&seq_names, bam, &Some(Box::new(ctrlc_wrapper)));
-    io::write_list(&read_names, &options.read_list)?;
-    fastq::subsample(
-        &read_names,
-        &options.fastq1,
-        &options.fastq2,
-        &options.fastq_out,
-        &options.suffix,
-        &Some(Box::new(ctrlc_wrapper)),
-    );
-    Ok(read_names.len())
+    let report = filter::filter_reads(options, &Some(Box::new(ctrlc_wrapper)))?;
+    filter::write_report(&report, &options.report)?;
+    Ok(report.reads_selected)
 }
 
-fn convert_hashmap_to_options(py: Python<'_>, map: HashMap<String, PyObject>) -> FilterOptions {
+fn convert_hashmap_to_options(
+    py: Python<'_>,
+    map: HashMap<String, PyObject>,
+) -> PyResult<FilterOptions> {
+    set_compression(py, &map);
     let list = extract_to_option_list(py, &map, "list");
     let list_file = extract_to_option_pathbuf(py, &map, "list_file");
+    let blobdir = extract_to_option_pathbuf(py, &map, "blobdir");
+    let filter = extract_to_string_vec(py, &map, "filter")?;
+    let partition = extract_to_option_pathbuf(py, &map, "partition");
+    let partition_field = extract_to_option_string(py, &map, "partition_field");
     let bam = extract_to_option_pathbuf(py, &map, "bam");
     let cram = extract_to_option_pathbuf(py, &map, "cram");
+    let pairs = extract_value_enum::<PairPolicy>(py, &map, "pairs")?.unwrap_or_default();
+    let invert = extract_to_bool(py, &map, "invert");
+    let stream = extract_to_bool(py, &map, "stream");
+    let index_dir = extract_to_option_pathbuf(py, &map, "index_dir");
     let fasta = extract_to_option_pathbuf(py, &map, "fasta");
+    let region = extract_to_string_vec(py, &map, "region")?;
+    let revcomp = extract_to_bool(py, &map, "revcomp");
     let fastq1 = extract_to_option_pathbuf(py, &map, "fastq1");
     let fastq2 = extract_to_option_pathbuf(py, &map, "fastq2");
+    let interleaved = extract_to_bool(py, &map, "interleaved");
+    let interleaved_out = extract_to_bool(py, &map, "interleaved_out");
+    let repair = extract_to_bool(py, &map, "repair");
+    let ubam = extract_to_option_pathbuf(py, &map, "ubam");
+    let ubam_out = extract_to_bool(py, &map, "ubam_out");
     let read_list = extract_to_option_pathbuf(py, &map, "read_list");
+    let bam_out = extract_to_option_pathbuf(py, &map, "bam_out");
+    let report = extract_to_option_pathbuf(py, &map, "report");
     let suffix = extract_to_default_string(py, &map, "suffix", "filtered");
     let fasta_out = extract_to_bool(py, &map, "fasta_out");
     let fastq_out = extract_to_bool(py, &map, "fastq_out");
-    FilterOptions {
+    let read_filter = extract_read_filter(py, &map)?;
+    Ok(FilterOptions {
         suffix,
         fasta_out,
         fastq_out,
         list,
         list_file,
+        blobdir,
+        filter,
+        partition,
+        partition_field,
         bam,
         cram,
+        pairs,
+        invert,
+        stream,
+        index_dir,
         fasta,
+        region,
+        revcomp,
         fastq1,
         fastq2,
+        interleaved,
+        interleaved_out,
+        repair,
+        ubam,
+        ubam_out,
         read_list,
-    }
+        bam_out,
+        report,
+        read_filter,
+    })
 }
 
 #[pyfunction]
 #[pyo3(signature = (**kwds))]
 pub fn fastx(py: Python<'_>, kwds: Option<HashMap<String, PyObject>>) -> PyResult<usize> {
     let options = match kwds {
-        Some(map) => convert_hashmap_to_options(py, map),
+        Some(map) => convert_hashmap_to_options(py, map)?,
         None => panic!["No arguments provided"],
     };
     fastx_with_options(&options, py)
@@ -2,8 +2,13 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::path::PathBuf;
 
+use clap::ValueEnum;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
+use crate::cli::{CompressionOptions, ReadFilter, Supplementary};
+use crate::io;
+
 pub fn extract_to_option_list(
     py: Python<'_>,
     map: &HashMap<String, PyObject>,
@@ -36,6 +41,50 @@ pub fn extract_to_option_pathbuf(
     option
 }
 
+pub fn extract_to_option_string(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+) -> Option<String> {
+    let hash_key = String::from(key);
+    let option: Option<String> = map
+        .get(&hash_key)
+        .map(|value| value.extract::<String>(py).unwrap());
+    option
+}
+
+/// Extract a path or list of paths, raising a `TypeError` for any other type.
+pub fn extract_to_pathbuf_vec(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+) -> PyResult<Vec<PathBuf>> {
+    let hash_key = String::from(key);
+    match map.get(&hash_key) {
+        Some(value) => match value.extract::<PathBuf>(py) {
+            Ok(path) => Ok(vec![path]),
+            Err(_) => value.extract::<Vec<PathBuf>>(py),
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+/// Extract a string or list of strings, raising a `TypeError` for any other type.
+pub fn extract_to_string_vec(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+) -> PyResult<Vec<String>> {
+    let hash_key = String::from(key);
+    match map.get(&hash_key) {
+        Some(value) => match value.extract::<String>(py) {
+            Ok(string) => Ok(vec![string]),
+            Err(_) => value.extract::<Vec<String>>(py),
+        },
+        _ => Ok(vec![]),
+    }
+}
+
 pub fn extract_to_default_string(
     py: Python<'_>,
     map: &HashMap<String, PyObject>,
@@ -59,6 +108,20 @@ pub fn extract_to_bool(py: Python<'_>, map: &HashMap<String, PyObject>, key: &st
     value
 }
 
+pub fn extract_to_default_usize(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+    default: usize,
+) -> usize {
+    let hash_key = String::from(key);
+    let value = match map.get(&hash_key) {
+        Some(value) => value.extract::<usize>(py).unwrap(),
+        _ => default,
+    };
+    value
+}
+
 pub fn extract_to_usize(py: Python<'_>, map: &HashMap<String, PyObject>, key: &str) -> usize {
     let hash_key = String::from(key);
     let value = match map.get(&hash_key) {
@@ -67,3 +130,108 @@ pub fn extract_to_usize(py: Python<'_>, map: &HashMap<String, PyObject>, key: &s
     };
     value
 }
+
+/// Extract an optional number, raising a `TypeError` for any other type.
+pub fn extract_to_option_f64(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+) -> PyResult<Option<f64>> {
+    let hash_key = String::from(key);
+    map.get(&hash_key)
+        .map(|value| value.extract::<f64>(py))
+        .transpose()
+}
+
+/// Extract a number or list of numbers, raising a `TypeError` for any other type.
+pub fn extract_to_f64_vec(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+) -> PyResult<Vec<f64>> {
+    let hash_key = String::from(key);
+    match map.get(&hash_key) {
+        Some(value) => match value.extract::<f64>(py) {
+            Ok(float) => Ok(vec![float]),
+            Err(_) => value.extract::<Vec<f64>>(py),
+        },
+        _ => Ok(vec![]),
+    }
+}
+
+/// Parse a string keyword argument as a clap value enum, raising a
+/// `ValueError` that lists the possible values when it doesn't match.
+pub fn extract_value_enum<T: ValueEnum>(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+    key: &str,
+) -> PyResult<Option<T>> {
+    let value = match map.get(key) {
+        Some(value) => value.extract::<String>(py)?,
+        None => return Ok(None),
+    };
+    // accept Python style underscores in kebab-case values, e.g. both_or_unmapped
+    match T::from_str(&value.replace('_', "-"), true) {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(_) => {
+            let names: Vec<String> = T::value_variants()
+                .iter()
+                .filter_map(|variant| variant.to_possible_value())
+                .map(|possible| possible.get_name().to_string())
+                .collect();
+            Err(PyValueError::new_err(format!(
+                "invalid {} {:?}, expected one of: {}",
+                key,
+                value,
+                names.join(", ")
+            )))
+        }
+    }
+}
+
+pub fn extract_read_filter(
+    py: Python<'_>,
+    map: &HashMap<String, PyObject>,
+) -> PyResult<ReadFilter> {
+    let mut read_filter = ReadFilter::default();
+    if let Some(value) = map.get("include_flags") {
+        read_filter.include_flags = value.extract::<u16>(py)?;
+    }
+    if let Some(value) = map.get("exclude_flags") {
+        read_filter.exclude_flags = value.extract::<u16>(py)?;
+    }
+    if let Some(value) = map.get("min_mapq") {
+        read_filter.min_mapq = value.extract::<u8>(py)?;
+    }
+    if let Some(value) = map.get("min_aligned_length") {
+        read_filter.min_aligned_length = value.extract::<usize>(py)?;
+    }
+    if let Some(value) = map.get("min_identity") {
+        read_filter.min_identity = value.extract::<f64>(py)?;
+    }
+    if let Some(supplementary) = extract_value_enum::<Supplementary>(py, map, "supplementary")? {
+        read_filter.supplementary = supplementary;
+    }
+    Ok(read_filter)
+}
+
+/// Apply `compression_level` and `compression_threads` keyword arguments
+/// to all `.gz` output files.
+pub fn set_compression(py: Python<'_>, map: &HashMap<String, PyObject>) {
+    let defaults = CompressionOptions::default();
+    io::set_compression(&CompressionOptions {
+        compression_level: extract_to_default_usize(
+            py,
+            map,
+            "compression_level",
+            defaults.compression_level as usize,
+        )
+        .min(9) as u32,
+        compression_threads: extract_to_default_usize(
+            py,
+            map,
+            "compression_threads",
+            defaults.compression_threads,
+        ),
+    });
+}
@@ -0,0 +1,179 @@
+//!
+//! A compact set of read names for very large read lists.
+//!
+//! Names are stored end to end in a single buffer and looked up by a
+//! 64-bit fingerprint, so each read costs its name plus around 20 bytes
+//! rather than a separate allocation per name. Lookups always compare the
+//! stored name, and names whose fingerprint collides with a different
+//! name are also indexed in a separate overflow map.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hash, Hasher};
+use std::mem::size_of;
+
+use crate::utils::format_si;
+
+/// Hasher for keys that are already well distributed 64-bit fingerprints.
+#[derive(Default)]
+struct FingerprintHasher(u64);
+
+impl Hasher for FingerprintHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = self.0.rotate_left(8) ^ *byte as u64;
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.0 = value;
+    }
+}
+
+fn fingerprint(name: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A set of read names.
+///
+/// # Examples
+///
+/// ```
+/// # use crate::blobtk::readset::ReadSet;
+/// let mut reads = ReadSet::new();
+/// assert!(reads.insert(b"read_1"));
+/// assert!(!reads.insert(b"read_1"));
+/// assert!(reads.insert(b"read_2"));
+/// assert!(reads.contains(b"read_2"));
+/// assert!(!reads.contains(b"read_3"));
+/// assert_eq!(reads.len(), 2);
+/// ```
+#[derive(Debug, Default)]
+pub struct ReadSet {
+    names: Vec<u8>,
+    ends: Vec<usize>,
+    index: HashMap<u64, u32, BuildHasherDefault<FingerprintHasher>>,
+    collisions: HashMap<Vec<u8>, u32>,
+}
+
+impl ReadSet {
+    pub fn new() -> ReadSet {
+        ReadSet::default()
+    }
+
+    fn name(&self, i: usize) -> &[u8] {
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        &self.names[start..self.ends[i]]
+    }
+
+    fn push(&mut self, name: &[u8]) -> u32 {
+        let i = u32::try_from(self.ends.len()).expect("too many reads for a read set");
+        self.names.extend_from_slice(name);
+        self.ends.push(self.names.len());
+        i
+    }
+
+    /// Add a name, returning `false` if it was already present.
+    pub fn insert(&mut self, name: &[u8]) -> bool {
+        let key = fingerprint(name);
+        match self.index.get(&key) {
+            Some(i) if self.name(*i as usize) == name => false,
+            Some(_) if self.collisions.contains_key(name) => false,
+            Some(_) => {
+                let i = self.push(name);
+                self.collisions.insert(name.to_vec(), i);
+                true
+            }
+            None => {
+                let i = self.push(name);
+                self.index.insert(key, i);
+                true
+            }
+        }
+    }
+
+    /// Position of a name in insertion order.
+    pub fn position(&self, name: &[u8]) -> Option<usize> {
+        match self.index.get(&fingerprint(name)) {
+            Some(i) if self.name(*i as usize) == name => Some(*i as usize),
+            Some(_) => self.collisions.get(name).map(|i| *i as usize),
+            None => None,
+        }
+    }
+
+    pub fn contains(&self, name: &[u8]) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over names in the order they were first inserted.
+    pub fn iter(&self) -> impl Iterator<Item = &[u8]> + '_ {
+        (0..self.ends.len()).map(|i| self.name(i))
+    }
+
+    /// A new set with the names that are not in `other`.
+    pub fn difference(&self, other: &ReadSet) -> ReadSet {
+        let mut reads = ReadSet::new();
+        for name in self.iter().filter(|name| !other.contains(name)) {
+            reads.insert(name);
+        }
+        reads
+    }
+
+    /// Approximate heap memory used by the set, in bytes.
+    pub fn heap_size(&self) -> usize {
+        self.names.capacity()
+            + self.ends.capacity() * size_of::<usize>()
+            + self.index.capacity() * (size_of::<(u64, u32)>() + 1)
+            + self
+                .collisions
+                .keys()
+                .map(|name| name.capacity() + size_of::<(Vec<u8>, u32)>())
+                .sum::<usize>()
+    }
+
+    /// Summary of the set size and memory use for progress output.
+    pub fn memory_message(&self) -> String {
+        let mut message = format!(
+            "{} reads in {}B",
+            format_si(&(self.len() as f64), 3),
+            format_si(&(self.heap_size() as f64), 3)
+        );
+        if let Some(peak) = peak_memory() {
+            message = format!("{}, peak memory {}B", message, format_si(&(peak as f64), 3));
+        }
+        message
+    }
+}
+
+impl<T: AsRef<[u8]>> FromIterator<T> for ReadSet {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> ReadSet {
+        let mut reads = ReadSet::new();
+        for name in iter {
+            reads.insert(name.as_ref());
+        }
+        reads
+    }
+}
+
+/// Peak resident memory of this process in bytes, where the platform
+/// reports it (`VmHWM` in `/proc/self/status` on Linux).
+pub fn peak_memory() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
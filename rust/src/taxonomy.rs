@@ -18,6 +18,9 @@ pub mod parse;
 /// Functions for name lookup.
 pub mod lookup;
 
+/// Functions for assigning taxa to sequences from similarity search hits.
+pub mod taxrule;
+
 pub use cli::TaxonomyOptions;
 
 pub use parse::{parse_taxdump, write_taxdump};
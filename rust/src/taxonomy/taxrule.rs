@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use crate::cli::TaxRule;
+use crate::error;
+use crate::io::get_reader;
+use crate::taxonomy::parse::Nodes;
+
+/// Ranks assigned by a taxrule, from highest to lowest.
+pub const RANKS: [&str; 8] = [
+    "superkingdom",
+    "kingdom",
+    "phylum",
+    "class",
+    "order",
+    "family",
+    "genus",
+    "species",
+];
+
+/// A similarity search hit from a BLAST/DIAMOND tabular file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    pub taxid: String,
+    pub score: f64,
+    pub start: usize,
+    pub end: usize,
+    pub subject: String,
+}
+
+/// The taxon assigned to a sequence at one rank.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    /// Summed bitscore of hits to the assigned taxon
+    pub score: f64,
+    /// Number of other taxa at this rank with hits to the sequence
+    pub cindex: usize,
+}
+
+impl Default for Assignment {
+    fn default() -> Assignment {
+        Assignment {
+            name: "no-hit".to_string(),
+            score: 0.0,
+            cindex: 0,
+        }
+    }
+}
+
+/// Read hits grouped by query sequence. Columns are `qseqid staxids
+/// bitscore`, optionally followed by the BLAST `std` columns (as written by
+/// `-outfmt "6 qseqid staxids bitscore std"`), which give the hit positions.
+/// Only the first of several `;` separated taxon IDs is used.
+pub fn parse_hits(path: &PathBuf) -> Result<HashMap<String, Vec<Hit>>, error::Error> {
+    let mut hits: HashMap<String, Vec<Hit>> = HashMap::new();
+    for (i, line) in get_reader(path)?.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let row: Vec<&str> = line.split('\t').collect();
+        let invalid = |message: &str| {
+            error::Error::ParseError(format!("{} line {}: {}", path.display(), i + 1, message))
+        };
+        if row.len() < 3 {
+            return Err(invalid("expected at least three columns"));
+        }
+        let taxid = row[1].split(';').next().unwrap_or("").to_string();
+        if taxid.is_empty() || taxid == "N/A" {
+            continue;
+        }
+        let score = row[2]
+            .parse::<f64>()
+            .map_err(|_| invalid("invalid bitscore"))?;
+        let position = |index: usize| row.get(index).and_then(|value| value.parse().ok());
+        let (start, end) = match (position(9), position(10)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => (0, 0),
+        };
+        hits.entry(row[0].to_string()).or_default().push(Hit {
+            taxid,
+            score,
+            start,
+            end,
+            subject: row.get(4).unwrap_or(&"").to_string(),
+        });
+    }
+    Ok(hits)
+}
+
+/// Name of a taxon at each rank in `RANKS`. Ranks missing from the lineage
+/// are named after the nearest higher assigned rank, e.g. `Chordata-undef`.
+/// Returns `None` for taxon IDs that are not in the taxonomy.
+pub fn rank_names(nodes: &Nodes, taxid: &String) -> Option<Vec<String>> {
+    let node = nodes.nodes.get(taxid)?;
+    let mut lineage = nodes.lineage(&"1".to_string(), taxid);
+    lineage.push(node);
+    let mut by_rank: HashMap<&str, String> = HashMap::new();
+    for node in lineage {
+        // current NCBI taxdumps use domain in place of superkingdom
+        let rank = match node.rank.as_str() {
+            "domain" => "superkingdom",
+            rank => rank,
+        };
+        let name = match node.scientific_name() {
+            name if name.is_empty() => node.tax_id(),
+            name => name,
+        };
+        by_rank.insert(rank, name);
+    }
+    let mut names: Vec<String> = vec![];
+    for rank in RANKS {
+        let name = match by_rank.remove(rank) {
+            Some(name) => name,
+            None => match names.last() {
+                Some(higher) if higher.ends_with("-undef") => higher.clone(),
+                Some(higher) => format!("{}-undef", higher),
+                None => "undef".to_string(),
+            },
+        };
+        names.push(name);
+    }
+    Some(names)
+}
+
+/// Assign a taxon at each rank to the taxon with the highest summed
+/// bitscore. Ties go to the taxon hit first.
+pub fn bestsum(hits: &[Hit], names: &HashMap<String, Vec<String>>) -> Vec<Assignment> {
+    (0..RANKS.len())
+        .map(|rank| {
+            let mut scores: Vec<(&String, f64)> = vec![];
+            for hit in hits {
+                let name = match names.get(&hit.taxid) {
+                    Some(names) => &names[rank],
+                    None => continue,
+                };
+                match scores.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, score)) => *score += hit.score,
+                    None => scores.push((name, hit.score)),
+                }
+            }
+            match scores
+                .iter()
+                .reduce(|best, next| if next.1 > best.1 { next } else { best })
+            {
+                Some((name, score)) => Assignment {
+                    name: name.to_string(),
+                    score: (score * 100.0).round() / 100.0,
+                    cindex: scores.len() - 1,
+                },
+                None => Assignment::default(),
+            }
+        })
+        .collect()
+}
+
+/// Assign taxa to each sequence from one or more sets of hits. With
+/// `bestsumorder`, hit sets are used in order of priority, taking the first
+/// set with hits to each sequence, while `bestsum` pools all hits.
+/// Returns the assignments at each rank and the hits used for each sequence.
+pub fn taxrule(
+    identifiers: &[String],
+    hit_sets: &[HashMap<String, Vec<Hit>>],
+    nodes: &Nodes,
+    rule: &TaxRule,
+) -> (Vec<Vec<Assignment>>, Vec<Vec<Hit>>) {
+    let mut names: HashMap<String, Vec<String>> = HashMap::new();
+    let mut unknown: HashSet<String> = HashSet::new();
+    for hit in hit_sets.iter().flat_map(|hits| hits.values().flatten()) {
+        if names.contains_key(&hit.taxid) || unknown.contains(&hit.taxid) {
+            continue;
+        }
+        match rank_names(nodes, &hit.taxid) {
+            Some(rank_names) => {
+                names.insert(hit.taxid.clone(), rank_names);
+            }
+            None => {
+                unknown.insert(hit.taxid.clone());
+            }
+        }
+    }
+    if !unknown.is_empty() {
+        eprintln!(
+            "{} taxon IDs in hits files are not in the taxonomy",
+            unknown.len()
+        );
+    }
+    let mut assignments = vec![];
+    let mut positions = vec![];
+    for seq_name in identifiers {
+        let known = |hits: &Vec<Hit>| -> Vec<Hit> {
+            hits.iter()
+                .filter(|hit| names.contains_key(&hit.taxid))
+                .cloned()
+                .collect()
+        };
+        let hits: Vec<Hit> = match rule {
+            TaxRule::BestSumOrder => hit_sets
+                .iter()
+                .filter_map(|hits| hits.get(seq_name))
+                .map(known)
+                .find(|hits| !hits.is_empty())
+                .unwrap_or_default(),
+            TaxRule::BestSum => hit_sets
+                .iter()
+                .filter_map(|hits| hits.get(seq_name))
+                .flat_map(known)
+                .collect(),
+        };
+        assignments.push(bestsum(&hits, &names));
+        positions.push(hits);
+    }
+    (assignments, positions)
+}
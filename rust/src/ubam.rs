@@ -0,0 +1,119 @@
+//!
+//! Filters reads from unaligned BAM files, such as PacBio HiFi reads with
+//! kinetics tags, writing either unaligned BAM or FASTQ.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use needletail::parser::{write_fastq, LineEnding};
+use rust_htslib::bam::record::Record;
+use rust_htslib::bam::{Format, Header, Read, Reader, Writer};
+
+use crate::error;
+use crate::fasta::reverse_complement;
+use crate::fastq::{suffix_file_name, trim_read_id};
+use crate::io::{get_writer, FileReport};
+use crate::readset::ReadSet;
+use crate::utils::styled_progress_bar;
+
+/// FASTQ output path for an unaligned BAM file, e.g. `reads.bam` is
+/// written to `reads.<suffix>.fastq.gz`.
+fn fastq_file_name(path: &Path, suffix: &String) -> PathBuf {
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    path.with_file_name(format!("{}.{}.fastq.gz", stem, suffix))
+}
+
+/// Sequence and phred+33 qualities of a record, in the orientation the
+/// read was sequenced.
+fn record_fastq(record: &Record) -> (Vec<u8>, Vec<u8>) {
+    let mut seq = record.seq().as_bytes();
+    let mut qual: Vec<u8> = record
+        .qual()
+        .iter()
+        .map(|q| if *q == 255 { b'!' } else { q + 33 })
+        .collect();
+    if record.is_reverse() {
+        seq = reverse_complement(&seq);
+        qual.reverse();
+    }
+    (seq, qual)
+}
+
+/// Write records of the listed reads from an unaligned BAM file to a new
+/// unaligned BAM file, keeping the header and all tags, and/or to FASTQ.
+pub fn subsample<F: Fn()>(
+    read_names: &ReadSet,
+    ubam_path: &Option<PathBuf>,
+    ubam_out: &bool,
+    fastq_out: &bool,
+    suffix: &String,
+    callback: &Option<F>,
+) -> Result<Vec<FileReport>, error::Error> {
+    let ubam_path = match ubam_path {
+        Some(path) => path,
+        None => return Ok(vec![]),
+    };
+    if !ubam_out && !fastq_out {
+        return Ok(vec![]);
+    }
+    let mut reader = Reader::from_path(ubam_path)?;
+    let mut bam_writer = if *ubam_out {
+        let header = Header::from_template(reader.header());
+        let out_path = suffix_file_name(ubam_path, suffix);
+        let writer = Writer::from_path(&out_path, &header, Format::Bam)?;
+        Some((writer, FileReport::new(&out_path)))
+    } else {
+        None
+    };
+    let mut fastq_writer: Option<(Box<dyn Write>, FileReport)> = if *fastq_out {
+        let out_path = fastq_file_name(ubam_path, suffix);
+        let report = FileReport::new(&out_path);
+        Some((get_writer(&Some(out_path)), report))
+    } else {
+        None
+    };
+
+    let total = read_names.len();
+    let progress_bar = styled_progress_bar(total, "Subsampling unaligned BAM");
+    let mut record = Record::new();
+    while let Some(result) = reader.read(&mut record) {
+        result?;
+        if read_names.contains(&trim_read_id(record.qname())) {
+            if let Some((writer, report)) = bam_writer.as_mut() {
+                writer.write(&record)?;
+                report.add(record.seq_len());
+            }
+            if let Some((writer, report)) = fastq_writer.as_mut() {
+                let (seq, qual) = record_fastq(&record);
+                write_fastq(
+                    record.qname(),
+                    &seq,
+                    Some(qual.as_slice()),
+                    writer,
+                    LineEnding::Unix,
+                )
+                .map_err(|err| {
+                    error::Error::WriteError(format!("unable to write FASTQ: {}", err))
+                })?;
+                report.add(seq.len());
+            }
+            // both records of a pair share a name, so count each name once
+            if !record.is_paired() || record.is_last_in_template() {
+                progress_bar.inc(1);
+                if progress_bar.position() as usize == total {
+                    break;
+                }
+            }
+        }
+        match callback {
+            Some(cb) => cb(),
+            None => (),
+        }
+    }
+    progress_bar.finish();
+    Ok(bam_writer
+        .map(|(_, report)| report)
+        .into_iter()
+        .chain(fastq_writer.map(|(_, report)| report))
+        .collect())
+}
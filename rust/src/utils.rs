@@ -95,7 +95,7 @@ pub fn indexed_sort<T: Ord>(list: &[T]) -> Vec<usize> {
 pub fn styled_progress_bar(total: usize, message: &str) -> ProgressBar {
     let progress_bar = ProgressBar::new(total as u64);
     let format_string = format!(
-        "[+]\t{}: {{bar:40.cyan/blue}} {{pos:>7}}/{{len:12}}",
+        "[+]\t{}: {{bar:40.cyan/blue}} {{pos:>7}}/{{len:12}} {{msg}}",
         message
     );
 
@@ -12,3 +12,35 @@ depth.bam_to_bed(bam="test/test.bam", bin_size=1000, bed="test/pytest.1000.bed")
 binned_covs = depth.bam_to_depth(bam="test/test.bam")
 for cov in binned_covs:
     print({cov.seq_name: cov.bins[0]})
+
+# generate a combined bed file of coverage depths for two labelled libraries
+depth.bam_to_bed(
+    bam=["test/test.bam", "test/test.bam"],
+    label=["lib1", "lib2"],
+    bin_size=1000,
+    bed="test/pytest.multi.bed",
+)
+
+# generate overlapping windows covering 10% of each sequence with a 5% step
+binned_covs = depth.bam_to_depth(bam="test/test.bam", window_size=[0.1], window_step=0.5)
+for cov in binned_covs:
+    print({cov.seq_name: list(zip(cov.starts, cov.ends, cov.bins))[:2]})
+
+# get median, standard deviation, covered fractions and read counts per contig
+binned_covs = depth.bam_to_depth(bam="test/test.bam", stats=True, thresholds=[1, 5, 10])
+for cov in binned_covs:
+    print({cov.seq_name: [cov.medians[0], cov.stdevs[0], cov.covered[0][0], cov.read_count]})
+
+# write per-base bedGraph, bigWig and quantized coverage tracks
+depth.bam_to_bed(
+    bam="test/test.bam",
+    bedgraph="test/pytest.bedgraph",
+    bigwig="test/pytest.bw",
+    quantized="test/pytest.quantized.bed",
+    quantize="0:1:5:150:",
+)
+
+# calculate coverage from minimap2 PAF alignments, without a BAM file
+binned_covs = depth.bam_to_depth(paf="test/test.paf.gz", bin_size=1000, min_mapq=10)
+for cov in binned_covs:
+    print({cov.seq_name: cov.bins[0]})
@@ -12,3 +12,35 @@ read_count = filter.fastx(
 )
 
 print(read_count)
+
+# keep reads that do not align to the listed sequences, including unmapped reads
+read_count = filter.fastx(
+    list_file="test/test.list",
+    bam="test/test.bam",
+    invert=True,
+    read_list="test/pytest.invert.list",
+)
+
+print(read_count)
+
+# filter sequences using BlobDir field filters in place of a list
+read_count = filter.fastx(
+    blobdir="test/minimal",
+    filter=["length--Min=1"],
+    fasta="test/minimal.fa",
+    fasta_out=True,
+    suffix="blobdir",
+)
+
+print(read_count)
+
+# write one set of FASTQ files per bin in a single pass
+read_count = filter.fastx(
+    partition="test/test.bins.tsv",
+    bam="test/test.bam",
+    fastq1="test/reads_1.fq.gz",
+    fastq2="test/reads_2.fq.gz",
+    fastq_out=True,
+)
+
+print(read_count)
@@ -14,6 +14,132 @@ CMD="./target/release/blobtk depth -b test/test.bam -s 1000 -O test/test.1000.be
 printf "\n\nrunning command\n$CMD\n\n"
 $CMD || exit 1
 
+CMD="./target/release/blobtk depth -b test/test.bam test/test.bam -l lib1 lib2 -s 1000 -O test/test.multi.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -w 0.1 0.01 100000 --window-step 0.5 -O test/test.windows.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -s 1000 --min-mapq 10 --supplementary exclude -O test/test.mapq10.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -s 1000 --engine cigar -O test/test.1000.cigar.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="cmp test/test.1000.bed test/test.1000.cigar.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+# test/deep.bam has 9000 reads over 100 bases of a 300 base contig that pass
+# --min-mapq 10, more than the htslib default maximum pileup depth of 8000
+CMD="./target/release/blobtk depth -b test/deep.bam --min-mapq 10 -O test/deep.pileup.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/deep.bam --min-mapq 10 --engine cigar -O test/deep.cigar.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="cmp test/deep.pileup.bed test/deep.cigar.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="grep -qx deep.0.300.3000.00 test/deep.pileup.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -s 1000 --threads 4 -O test/test.1000.threads.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="cmp test/test.1000.bed test/test.1000.threads.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -s 1000 --stats --thresholds 1 5 10 -O test/test.1000.stats.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -i test/test.list --bedgraph test/test.bedgraph --bigwig test/test.bw --quantized test/test.quantized.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth --paf test/test.paf.gz -s 1000 --min-mapq 10 -O test/test.paf.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk depth -b test/test.bam -s 1000 --stream -O test/test.1000.stream.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="cmp test/test.1000.bed test/test.1000.stream.bed"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b - --stream -O test/test.stream.list"
+printf "\n\nrunning command\n$CMD < test/test.bam\n\n"
+$CMD < test/test.bam || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam --index-dir test/index -O test/test.index.list"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam --invert -O test/test.invert.list"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam --pairs both-or-unmapped -O test/test.pairs.list"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam --bam-out test/test.filtered.bam --report test/test.filter.report.json"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter --partition test/test.bins.tsv -b test/test.bam -f test/reads_1.fq.gz -r test/reads_2.fq.gz -F -O test/test.bins.reads.tsv"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam -f test/reads_1.fq.gz -r test/reads_2.fq.gz -F --repair --interleaved-out -S interleaved"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam -f test/reads_1.interleaved.fq.gz --interleaved -F -S reinterleaved"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam -f test/reads_1.fq.gz -r test/reads_2.fq.gz -F -S bgzf --compression-level 1 --compression-threads 2"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam --ubam test/reads.bam --ubam-out -F -S ubam"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -d test/minimal --filter length--Min=1 --filter identifiers--Regex=^seq||!length--Min=1 -a test/minimal.fa -A -S blobdir"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk filter -a test/minimal.fa --region seq1:2-5 -A --revcomp -S region"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk create -a test/minimal.fa -d test/created --replace"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk add -d test/created --cov test/test.multi.bed --text test/minimal.fields.tsv --busco test/full_table.tsv --hits test/minimal.hits.tsv --taxdump test/ncbi_taxdump"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
+CMD="./target/release/blobtk plot -d test/created -v snail -o test/created.snail.svg"
+printf "\n\nrunning command\n$CMD\n\n"
+$CMD || exit 1
+
 CMD="./target/release/blobtk filter -i test/test.list -b test/test.bam -f test/reads_1.fq.gz -r test/reads_2.fq.gz -F"
 printf "\n\nrunning command\n$CMD\n\n"
 $CMD || exit 1